@@ -1,7 +1,111 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT};
+use anchor_lang::solana_program::sysvar::instructions as instructions_sysvar;
 use anchor_spl::token::{self, Mint, Token, TokenAccount};
-
-declare_id!("TokenizationProgram11111111111111111111111");
+use anchor_spl::token_2022::spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use anchor_spl::token_2022::{spl_token_2022, Token2022};
+use anchor_spl::token_interface::spl_pod::optional_keys::OptionalNonZeroPubkey;
+use anchor_spl::token_interface::spl_token_metadata_interface::state::{Field, TokenMetadata};
+use anchor_spl::token_interface::{self, TokenInterface};
+
+declare_id!("CCjw6m6w8FoCyXciLChoUiEtXaoSsk8tfGMEi7rFVZPt");
+
+/// Compliance transfer hook installed on every vault mint (`programs/transfer-hook`)
+pub const TRANSFER_HOOK_PROGRAM_ID: Pubkey = pubkey!("aNHP1kA5XMUh7ThwMB715gNJc5qczNdW19eYgZGeQba");
+
+pub const OFFERING_SEED: &[u8] = b"offering";
+pub const OFFERING_ESCROW_SEED: &[u8] = b"offering_escrow";
+pub const BATCH_AUCTION_SEED: &[u8] = b"batch_auction";
+pub const BATCH_AUCTION_ESCROW_SEED: &[u8] = b"batch_auction_escrow";
+pub const SEALED_BID_SEED: &[u8] = b"sealed_bid";
+pub const VESTING_AUTHORITY_SEED: &[u8] = b"vesting_authority";
+pub const VESTING_ESCROW_SEED: &[u8] = b"vesting_escrow";
+pub const PROTOCOL_CONFIG_SEED: &[u8] = b"protocol_config";
+pub const INVESTOR_RECORD_SEED: &[u8] = b"investor";
+pub const VAULT_SIGNER_SEED: &[u8] = b"vault_signer";
+pub const HOOK_AUTHORITY_SEED: &[u8] = b"hook_authority";
+pub const PROTOCOL_FEE_SEED: &[u8] = b"protocol_fee";
+pub const AUTHORITY_FEE_SEED: &[u8] = b"authority_fee";
+pub const LP_MINT_SEED: &[u8] = b"lp_mint";
+pub const LP_POSITION_SEED: &[u8] = b"lp_position";
+pub const LP_SHARE_ESCROW_SEED: &[u8] = b"lp_share_escrow";
+pub const DISCOUNT_BOOK_SEED: &[u8] = b"discount_book";
+pub const BAND_POSITION_SEED: &[u8] = b"band_position";
+pub const ORDER_BOOK_SEED: &[u8] = b"order_book";
+pub const ORDER_BOOK_BASE_SEED: &[u8] = b"order_book_base";
+pub const ORDER_BOOK_QUOTE_SEED: &[u8] = b"order_book_quote";
+pub const OPEN_ORDERS_SEED: &[u8] = b"open_orders";
+pub const RFQ_AUTHORITY_SEED: &[u8] = b"rfq_authority";
+pub const RFQ_NONCE_SEED: &[u8] = b"rfq_nonce";
+pub const PRICE_OBSERVATIONS_SEED: &[u8] = b"price_observations";
+pub const LENDING_RESERVE_SEED: &[u8] = b"lending_reserve";
+pub const RESERVE_COLLATERAL_SEED: &[u8] = b"reserve_collateral";
+pub const RESERVE_LIQUIDITY_SEED: &[u8] = b"reserve_liquidity";
+pub const LENDING_DEPOSIT_SEED: &[u8] = b"lending_deposit";
+pub const OBLIGATION_SEED: &[u8] = b"obligation";
+pub const REPO_AGREEMENT_SEED: &[u8] = b"repo_agreement";
+pub const REPO_ESCROW_SEED: &[u8] = b"repo_escrow";
+
+pub const HOLDING_SEED: &[u8] = b"holding";
+pub const MAX_VAULT_NAME_LEN: usize = 60;
+pub const MAX_TOKEN_SYMBOL_LEN: usize = 10;
+pub const MAX_TOKEN_URI_LEN: usize = 200;
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Cap on each protocol or vault-authority fee, in basis points
+pub const MAX_FEE_BPS: u16 = 1_000;
+/// LP fee growth is a Q64.64 fixed-point amount per LP share
+const FEE_GROWTH_SHIFT: u32 = 64;
+
+/// Shares locked out of the first deposit so the share price cannot be inflated from a dust supply
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+/// Bounds on a StableSwap pool's amplification coefficient
+pub const MIN_AMP: u64 = 1;
+pub const MAX_AMP: u64 = 1_000;
+/// An amplification ramp must last at least a day and may change the coefficient at most tenfold
+pub const MIN_AMP_RAMP_DURATION: i64 = 24 * 60 * 60;
+pub const MAX_AMP_CHANGE: u64 = 10;
+/// Newton iterations allowed for the StableSwap invariant to converge
+const STABLE_SWAP_MAX_ITERATIONS: usize = 64;
+
+/// Discount ticks are whole percentages below par, 0% through 99%
+pub const DISCOUNT_TICKS: usize = 100;
+/// A fully filled discount tick, Q64.64
+const TICK_FILL_ONE: u128 = 1 << 64;
+
+/// Resting orders per side of a vault's order book, and fills awaiting the consume crank
+pub const MAX_ORDERS_PER_SIDE: usize = 32;
+pub const MAX_FILL_EVENTS: usize = 32;
+
+/// Accounts per hop of `route_swap`, and the longest path it takes
+pub const ROUTE_HOP_ACCOUNTS: usize = 14;
+pub const MAX_ROUTE_HOPS: usize = 4;
+
+/// A pool's observation ring buffer holds this many snapshots, at most one per spacing interval
+pub const MAX_PRICE_OBSERVATIONS: usize = 128;
+pub const PRICE_OBSERVATION_SPACING: i64 = 60;
+/// StableSwap spot prices are sampled with a trade of this share of the input reserve, in basis points
+const STABLE_PRICE_PROBE_BPS: u64 = 1;
+
+/// Flash loans pay this fee in basis points, rounded up, which accrues to the pool's LPs
+pub const FLASH_LOAN_FEE_BPS: u64 = 9;
+
+/// A vault's monthly payments fall due this far apart, counted from its creation
+pub const SECONDS_PER_MONTH: i64 = 30 * 24 * 60 * 60;
+pub const SECONDS_PER_YEAR: i64 = 365 * 24 * 60 * 60;
+/// Each missed monthly payment cuts a lending reserve's LTV and liquidation threshold by this share
+pub const DELINQUENCY_HAIRCUT_BPS: u64 = 2_500;
+/// Largest share of an unhealthy obligation's debt a single liquidation may repay
+pub const LIQUIDATION_CLOSE_FACTOR_BPS: u64 = 5_000;
+/// A lending reserve's borrow index is Q64.64
+const BORROW_INDEX_ONE: u128 = 1 << 64;
+
+/// LP commitment windows run back to back from pool creation
+pub const LP_WINDOW_DURATION: i64 = 90 * 24 * 60 * 60;
+/// LPs may re-quote their forward discount once per period within a window
+pub const LP_QUOTE_PERIOD: i64 = 30 * 24 * 60 * 60;
 
 #[program]
 pub mod meteora_tokenization {
@@ -12,15 +116,48 @@ pub mod meteora_tokenization {
     /// Create a new RWA vault with tokenized cash flows
     pub fn create_vault(
         ctx: Context<CreateVault>,
-        principal: u64,
-        total_expected_interest: u64,
-        monthly_payment: u64,
-        total_months: u32,
+        terms: VaultTerms,
         vault_name: String,
+        symbol: String,
+        uri: String,
     ) -> Result<()> {
+        require!(vault_name.len() <= MAX_VAULT_NAME_LEN, MeteraError::InvalidMetadata);
+        require!(symbol.len() <= MAX_TOKEN_SYMBOL_LEN, MeteraError::InvalidMetadata);
+        require!(uri.len() <= MAX_TOKEN_URI_LEN, MeteraError::InvalidMetadata);
+
+        // Metadata lives on the mint itself; the vault signer PDA is its update authority
+        let metadata = TokenMetadata {
+            update_authority: OptionalNonZeroPubkey::try_from(Some(ctx.accounts.vault_signer.key()))?,
+            mint: ctx.accounts.token_mint.key(),
+            name: vault_name.clone(),
+            symbol: symbol.clone(),
+            uri: uri.clone(),
+            additional_metadata: Vec::new(),
+        };
+        fund_mint_growth(&ctx.accounts.token_mint, &ctx.accounts.authority, &ctx.accounts.system_program, metadata.tlv_size_of()?)?;
+
+        token_interface::token_metadata_initialize(
+            CpiContext::new(
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::TokenMetadataInitialize {
+                    token_program_id: ctx.accounts.vault_token_program.to_account_info(),
+                    metadata: ctx.accounts.token_mint.to_account_info(),
+                    update_authority: ctx.accounts.vault_signer.to_account_info(),
+                    mint_authority: ctx.accounts.authority.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                },
+            ),
+            metadata.name,
+            metadata.symbol,
+            metadata.uri,
+        )?;
+
+        let VaultTerms { principal, total_expected_interest, monthly_payment, total_months } = terms;
         let vault = &mut ctx.accounts.vault;
         vault.authority = ctx.accounts.authority.key();
+        vault.compliance_officer = ctx.accounts.authority.key();
         vault.token_mint = ctx.accounts.token_mint.key();
+        vault.payment_mint = ctx.accounts.payment_mint.key();
         vault.vault_treasury = ctx.accounts.vault_treasury.key();
         vault.principal = principal;
         vault.total_expected_interest = total_expected_interest;
@@ -32,6 +169,13 @@ pub mod meteora_tokenization {
         vault.created_at = Clock::get()?.unix_timestamp;
         vault.total_redeemed = 0;
         vault.is_active = true;
+        vault.offering_count = 0;
+        vault.active_offering = Pubkey::default();
+        vault.auction_count = 0;
+        vault.max_holding_per_wallet = 0;
+        vault.max_holder_count = 0;
+        vault.holder_count = 0;
+        vault.authority_fees = FeeSchedule::default();
 
         emit!(VaultCreated {
             vault: vault.key(),
@@ -42,20 +186,96 @@ pub mod meteora_tokenization {
             total_months,
         });
 
+        emit!(VaultMetadataUpdated {
+            vault: vault.key(),
+            mint: vault.token_mint,
+            name: vault.vault_name.clone(),
+            symbol,
+            uri,
+        });
+
+        Ok(())
+    }
+
+    /// Update the name, symbol or URI stored in the vault mint's metadata
+    pub fn update_vault_metadata(
+        ctx: Context<UpdateVaultMetadata>,
+        name: Option<String>,
+        symbol: Option<String>,
+        uri: Option<String>,
+    ) -> Result<()> {
+        let mut updates = Vec::new();
+        if let Some(name) = name {
+            require!(name.len() <= MAX_VAULT_NAME_LEN, MeteraError::InvalidMetadata);
+            updates.push((Field::Name, name));
+        }
+        if let Some(symbol) = symbol {
+            require!(symbol.len() <= MAX_TOKEN_SYMBOL_LEN, MeteraError::InvalidMetadata);
+            updates.push((Field::Symbol, symbol));
+        }
+        if let Some(uri) = uri {
+            require!(uri.len() <= MAX_TOKEN_URI_LEN, MeteraError::InvalidMetadata);
+            updates.push((Field::Uri, uri));
+        }
+        require!(!updates.is_empty(), MeteraError::InvalidMetadata);
+
+        let mut metadata = {
+            let mint_info = ctx.accounts.token_mint.to_account_info();
+            let mint_data = mint_info.try_borrow_data()?;
+            let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+            mint_state.get_variable_len_extension::<TokenMetadata>()?
+        };
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+
+        for (field, value) in updates {
+            let previous_size = metadata.tlv_size_of()?;
+            metadata.update(field.clone(), value.clone());
+            let growth = metadata.tlv_size_of()?.saturating_sub(previous_size);
+            fund_mint_growth(&ctx.accounts.token_mint, &ctx.accounts.authority, &ctx.accounts.system_program, growth)?;
+
+            token_interface::token_metadata_update_field(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token_interface::TokenMetadataUpdateField {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        metadata: ctx.accounts.token_mint.to_account_info(),
+                        update_authority: ctx.accounts.vault_signer.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                field,
+                value,
+            )?;
+        }
+
+        let vault = &mut ctx.accounts.vault;
+        vault.vault_name = metadata.name.clone();
+
+        emit!(VaultMetadataUpdated {
+            vault: vault_key,
+            mint: vault.token_mint,
+            name: metadata.name,
+            symbol: metadata.symbol,
+            uri: metadata.uri,
+        });
+
         Ok(())
     }
 
     /// Mint tokens representing cash flow claims
     pub fn mint_tokens(ctx: Context<MintTokens>, amount: u64) -> Result<()> {
-        let vault = &ctx.accounts.vault;
+        let vault = &mut ctx.accounts.vault;
 
         require!(vault.is_active, MeteraError::VaultInactive);
         require!(amount <= vault.total_tokens_minted, MeteraError::ExceedsTokenSupply);
+        vault.record_incoming(&mut ctx.accounts.destination_holding, amount)?;
 
-        token::mint_to(
+        token_interface::mint_to(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                token::MintTo {
+                token_interface::MintTo {
                     mint: ctx.accounts.token_mint.to_account_info(),
                     to: ctx.accounts.destination.to_account_info(),
                     authority: ctx.accounts.authority.to_account_info(),
@@ -80,21 +300,129 @@ pub mod meteora_tokenization {
         ctx: Context<PurchaseTokensPrimary>,
         token_amount: u64,
         discount_percentage: u8,
+        vesting: Option<VestingTerms>,
     ) -> Result<()> {
         require!(discount_percentage <= 100, MeteraError::InvalidDiscount);
+        if let Some(terms) = &vesting {
+            terms.validate()?;
+        }
 
-        let vault = &ctx.accounts.vault;
+        let vault = &mut ctx.accounts.vault;
         require!(vault.is_active, MeteraError::VaultInactive);
 
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.investor_record.check_eligible(current_time)?;
+
+        // While an offering is open every primary purchase must go through it
+        match ctx.accounts.primary_offering.as_ref() {
+            Some(offering) => require_keys_eq!(offering.key(), vault.active_offering, MeteraError::OfferingRequired),
+            None => require_keys_eq!(vault.active_offering, Pubkey::default(), MeteraError::OfferingRequired),
+        }
+
+        // Escrowed offerings hold the payment until the raise is finalized
+        if let Some(offering) = ctx.accounts.primary_offering.as_mut() {
+            require!(offering.state == OfferingState::Open, MeteraError::OfferingNotOpen);
+            require!(current_time < offering.deadline, MeteraError::OfferingClosed);
+            require!(vesting == offering.vesting, MeteraError::VestingTermsMismatch);
+
+            // Reserve the buyer's holding now so the caps cannot block settlement once the payment is escrowed
+            if offering.vesting.is_none() {
+                vault.record_incoming(&mut ctx.accounts.buyer_holding, token_amount)?;
+            }
+
+            // Dutch auctions sell at the rising auction discount (falling price); the buyer's discount is the least they accept
+            let discount_percentage = match offering.pricing {
+                OfferingPricing::FixedDiscount => discount_percentage,
+                OfferingPricing::DutchAuction => {
+                    let auction_discount = offering.current_discount(current_time)?;
+                    require!(auction_discount >= discount_percentage, MeteraError::AuctionDiscountBelowLimit);
+                    auction_discount
+                }
+            };
+            let purchase_price = discounted_price(token_amount, discount_percentage)?;
+
+            let offering_escrow = ctx.accounts.offering_escrow.as_ref().ok_or(MeteraError::InvalidOfferingEscrow)?;
+            require_keys_eq!(offering_escrow.key(), offering.escrow, MeteraError::InvalidOfferingEscrow);
+
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.buyer_payment_account.to_account_info(),
+                        to: offering_escrow.to_account_info(),
+                        authority: ctx.accounts.buyer.to_account_info(),
+                    },
+                ),
+                purchase_price,
+            )?;
+
+            offering.total_committed = offering.total_committed.checked_add(purchase_price).ok_or(MeteraError::ArithmeticOverflow)?;
+            offering.total_tokens_sold = offering.total_tokens_sold.checked_add(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+            if offering.token_cap > 0 {
+                require!(offering.total_tokens_sold <= offering.token_cap, MeteraError::ExceedsOfferingCap);
+
+                // The sale that fills the cap sets the uniform clearing price
+                if offering.total_tokens_sold == offering.token_cap {
+                    offering.clearing_discount_percentage = discount_percentage;
+                }
+            }
+
+            let primary_sale = &mut ctx.accounts.primary_sale;
+            primary_sale.vault = vault.key();
+            primary_sale.buyer = ctx.accounts.buyer.key();
+            primary_sale.token_amount = token_amount;
+            primary_sale.purchase_price = purchase_price;
+            primary_sale.discount_percentage = discount_percentage;
+            primary_sale.purchased_at = current_time;
+            primary_sale.offering = offering.key();
+            primary_sale.status = PrimarySaleStatus::Escrowed;
+            primary_sale.set_vesting(offering.vesting, 0);
+
+            emit!(TokensEscrowed {
+                vault: vault.key(),
+                offering: offering.key(),
+                buyer: ctx.accounts.buyer.key(),
+                token_amount,
+                purchase_price,
+                discount_percentage,
+            });
+
+            return Ok(());
+        }
+
         // Calculate purchase price with discount
-        let par_value = token_amount;
-        let discount_amount = (par_value as u128)
-            .checked_mul(discount_percentage as u128)
-            .ok_or(MeteraError::ArithmeticOverflow)?
-            .checked_div(100)
-            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        let purchase_price = discounted_price(token_amount, discount_percentage)?;
+
+        // Vesting purchases are minted into the vault's vesting escrow instead of the buyer's wallet
+        if vesting.is_some() {
+            require_keys_eq!(
+                ctx.accounts.buyer_token_account.key(),
+                vesting_escrow_address(&vault.key()),
+                MeteraError::InvalidVestingEscrow
+            );
+        } else {
+            require_keys_eq!(ctx.accounts.buyer_token_account.owner, ctx.accounts.buyer.key(), MeteraError::InvalidTokenAccountOwner);
+            vault.record_incoming(&mut ctx.accounts.buyer_holding, token_amount)?;
+        }
 
-        let purchase_price = par_value.checked_sub(discount_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        // Origination fee comes out of the purchase price
+        let fee = FeeCharge::new(
+            FeeKind::Origination,
+            purchase_price,
+            ctx.accounts.protocol_config.protocol_fees.origination_fee_bps,
+            vault.authority_fees.origination_fee_bps,
+        )?;
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.buyer_payment_account.to_account_info(),
+            ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault.key(),
+            &fee,
+            &[],
+        )?;
 
         // Transfer payment from buyer to vault treasury
         token::transfer(
@@ -106,14 +434,14 @@ pub mod meteora_tokenization {
                     authority: ctx.accounts.buyer.to_account_info(),
                 },
             ),
-            purchase_price,
+            fee.net()?,
         )?;
 
         // Mint tokens to buyer
-        token::mint_to(
+        token_interface::mint_to(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                token::MintTo {
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::MintTo {
                     mint: ctx.accounts.token_mint.to_account_info(),
                     to: ctx.accounts.buyer_token_account.to_account_info(),
                     authority: ctx.accounts.authority.to_account_info(),
@@ -129,7 +457,10 @@ pub mod meteora_tokenization {
         primary_sale.token_amount = token_amount;
         primary_sale.purchase_price = purchase_price;
         primary_sale.discount_percentage = discount_percentage;
-        primary_sale.purchased_at = Clock::get()?.unix_timestamp;
+        primary_sale.purchased_at = current_time;
+        primary_sale.offering = Pubkey::default();
+        primary_sale.status = PrimarySaleStatus::Settled;
+        primary_sale.set_vesting(vesting, current_time);
 
         emit!(TokensPurchased {
             vault: vault.key(),
@@ -142,666 +473,7990 @@ pub mod meteora_tokenization {
         Ok(())
     }
 
-    // ============ MONTHLY REPAYMENT CYCLE ============
+    /// Open an escrowed primary offering with a soft cap and deadline
+    pub fn create_primary_offering(
+        ctx: Context<CreatePrimaryOffering>,
+        soft_cap: u64,
+        deadline: i64,
+        vesting: Option<VestingTerms>,
+    ) -> Result<()> {
+        if let Some(terms) = &vesting {
+            terms.validate()?;
+        }
 
-    /// Receive monthly payment into the vault
-    pub fn receive_monthly_payment(ctx: Context<ReceiveMonthlyPayment>, payment_amount: u64) -> Result<()> {
         let vault = &mut ctx.accounts.vault;
         require!(vault.is_active, MeteraError::VaultInactive);
-        require!(vault.current_month < vault.total_months, MeteraError::VaultMatured);
+        require_keys_eq!(vault.active_offering, Pubkey::default(), MeteraError::OfferingAlreadyActive);
 
-        // Verify payment matches expected monthly payment
-        require!(payment_amount == vault.monthly_payment, MeteraError::InvalidPaymentAmount);
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(deadline > current_time, MeteraError::InvalidDeadline);
+
+        let offering = &mut ctx.accounts.primary_offering;
+        offering.vault = vault.key();
+        offering.index = vault.offering_count;
+        offering.payment_mint = ctx.accounts.payment_mint.key();
+        offering.escrow = ctx.accounts.offering_escrow.key();
+        offering.soft_cap = soft_cap;
+        offering.deadline = deadline;
+        offering.total_committed = 0;
+        offering.total_tokens_sold = 0;
+        offering.state = OfferingState::Open;
+        offering.created_at = current_time;
+        offering.bump = ctx.bumps.primary_offering;
+        offering.pricing = OfferingPricing::FixedDiscount;
+        offering.settlement = AuctionSettlement::PayAsBid;
+        offering.token_cap = 0;
+        offering.start_discount_percentage = 0;
+        offering.max_discount_percentage = 0;
+        offering.clearing_discount_percentage = 0;
+        offering.vesting = vesting;
+
+        vault.offering_count = vault.offering_count.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+        vault.active_offering = offering.key();
+
+        emit!(PrimaryOfferingCreated {
+            vault: vault.key(),
+            offering: offering.key(),
+            soft_cap,
+            deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Open an escrowed Dutch auction whose discount rises from the start discount to the max discount by the deadline,
+    /// so the price falls to its floor
+    pub fn create_dutch_auction_offering(
+        ctx: Context<CreatePrimaryOffering>,
+        soft_cap: u64,
+        deadline: i64,
+        auction: DutchAuctionTerms,
+        vesting: Option<VestingTerms>,
+    ) -> Result<()> {
+        auction.validate()?;
+        if let Some(terms) = &vesting {
+            terms.validate()?;
+        }
+        let DutchAuctionTerms { token_cap, start_discount_percentage, max_discount_percentage, settlement } = auction;
+
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+        require_keys_eq!(vault.active_offering, Pubkey::default(), MeteraError::OfferingAlreadyActive);
+        require!(token_cap <= vault.total_tokens_minted, MeteraError::ExceedsTokenSupply);
+
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(deadline > current_time, MeteraError::InvalidDeadline);
+
+        let offering = &mut ctx.accounts.primary_offering;
+        offering.vault = vault.key();
+        offering.index = vault.offering_count;
+        offering.payment_mint = ctx.accounts.payment_mint.key();
+        offering.escrow = ctx.accounts.offering_escrow.key();
+        offering.soft_cap = soft_cap;
+        offering.deadline = deadline;
+        offering.total_committed = 0;
+        offering.total_tokens_sold = 0;
+        offering.state = OfferingState::Open;
+        offering.created_at = current_time;
+        offering.bump = ctx.bumps.primary_offering;
+        offering.pricing = OfferingPricing::DutchAuction;
+        offering.settlement = settlement;
+        offering.token_cap = token_cap;
+        offering.start_discount_percentage = start_discount_percentage;
+        offering.max_discount_percentage = max_discount_percentage;
+        offering.clearing_discount_percentage = max_discount_percentage;
+        offering.vesting = vesting;
+
+        vault.offering_count = vault.offering_count.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+        vault.active_offering = offering.key();
+
+        emit!(PrimaryOfferingCreated {
+            vault: vault.key(),
+            offering: offering.key(),
+            soft_cap,
+            deadline,
+        });
+
+        emit!(DutchAuctionStarted {
+            vault: vault.key(),
+            offering: offering.key(),
+            token_cap,
+            start_discount_percentage,
+            max_discount_percentage,
+            settlement,
+        });
+
+        Ok(())
+    }
+
+    /// Close an offering after its deadline (or once sold out), succeeding only if the soft cap was reached
+    pub fn finalize_primary_offering(ctx: Context<FinalizePrimaryOffering>) -> Result<()> {
+        let offering = &mut ctx.accounts.primary_offering;
+        require!(offering.state == OfferingState::Open, MeteraError::OfferingNotOpen);
+
+        let current_time = Clock::get()?.unix_timestamp;
+        let sold_out = offering.token_cap > 0 && offering.total_tokens_sold >= offering.token_cap;
+        require!(current_time >= offering.deadline || sold_out, MeteraError::OfferingStillOpen);
+
+        // Uniform clearing charges every buyer the final auction price, so measure the raise at that price
+        let proceeds = if offering.is_uniform_clearing() {
+            discounted_price(offering.total_tokens_sold, offering.clearing_discount_percentage)?
+        } else {
+            offering.total_committed
+        };
+
+        let succeeded = proceeds >= offering.soft_cap;
+        offering.state = if succeeded { OfferingState::Succeeded } else { OfferingState::Failed };
+        ctx.accounts.vault.active_offering = Pubkey::default();
+
+        if offering.pricing == OfferingPricing::DutchAuction {
+            emit!(DutchAuctionCleared {
+                vault: offering.vault,
+                offering: offering.key(),
+                clearing_discount_percentage: offering.clearing_discount_percentage,
+                settlement: offering.settlement,
+                total_tokens_sold: offering.total_tokens_sold,
+                proceeds,
+            });
+        }
+
+        emit!(PrimaryOfferingFinalized {
+            vault: offering.vault,
+            offering: offering.key(),
+            total_committed: offering.total_committed,
+            total_tokens_sold: offering.total_tokens_sold,
+            soft_cap: offering.soft_cap,
+            succeeded,
+        });
+
+        Ok(())
+    }
+
+    /// Release an escrowed purchase from a successful offering: pay the treasury and mint to the buyer
+    pub fn settle_primary_sale(ctx: Context<SettlePrimarySale>) -> Result<()> {
+        let offering = &ctx.accounts.primary_offering;
+        require!(offering.state == OfferingState::Succeeded, MeteraError::OfferingNotSucceeded);
+
+        let primary_sale = &mut ctx.accounts.primary_sale;
+        require!(primary_sale.status == PrimarySaleStatus::Escrowed, MeteraError::SaleNotEscrowed);
+
+        let buyer_token_account = &ctx.accounts.buyer_token_account;
+        if primary_sale.is_vesting() {
+            require_keys_eq!(
+                buyer_token_account.key(),
+                vesting_escrow_address(&offering.vault),
+                MeteraError::InvalidVestingEscrow
+            );
+        } else {
+            // The holding was reserved when the payment was escrowed
+            require_keys_eq!(buyer_token_account.owner, primary_sale.buyer, MeteraError::InvalidTokenAccountOwner);
+        }
+
+        // Uniform clearing reprices every bid at the clearing discount and refunds the difference
+        let settled_discount = if offering.is_uniform_clearing() {
+            offering.clearing_discount_percentage
+        } else {
+            primary_sale.discount_percentage
+        };
+        let settled_price = discounted_price(primary_sale.token_amount, settled_discount)?;
+        let refund_amount = primary_sale.purchase_price.checked_sub(settled_price).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let vault_key = offering.vault;
+        let offering_index = offering.index.to_le_bytes();
+        let seeds: &[&[u8]] = &[OFFERING_SEED, vault_key.as_ref(), &offering_index, &[offering.bump]];
+
+        let fee = FeeCharge::new(
+            FeeKind::Origination,
+            settled_price,
+            ctx.accounts.protocol_config.protocol_fees.origination_fee_bps,
+            ctx.accounts.vault.authority_fees.origination_fee_bps,
+        )?;
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.offering_escrow.to_account_info(),
+            offering.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault_key,
+            &fee,
+            &[seeds],
+        )?;
 
-        // Transfer payment to vault treasury
         token::transfer(
-            CpiContext::new(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.payer_account.to_account_info(),
+                    from: ctx.accounts.offering_escrow.to_account_info(),
                     to: ctx.accounts.vault_treasury.to_account_info(),
-                    authority: ctx.accounts.payer.to_account_info(),
+                    authority: offering.to_account_info(),
                 },
+                &[seeds],
             ),
-            payment_amount,
+            fee.net()?,
         )?;
 
-        // Record payment
-        let payment = &mut ctx.accounts.monthly_payment_record;
-        payment.vault = vault.key();
-        payment.month = vault.current_month;
-        payment.amount = payment_amount;
-        payment.received_at = Clock::get()?.unix_timestamp;
-        payment.available_for_redemption = payment_amount;
+        if refund_amount > 0 {
+            let buyer_payment_account = ctx.accounts.buyer_payment_account.as_ref().ok_or(MeteraError::MissingRefundAccount)?;
+
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.offering_escrow.to_account_info(),
+                        to: buyer_payment_account.to_account_info(),
+                        authority: offering.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                refund_amount,
+            )?;
+        }
 
-        vault.current_month = vault.current_month.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+        token_interface::mint_to(
+            CpiContext::new(
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::MintTo {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.buyer_token_account.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            primary_sale.token_amount,
+        )?;
 
-        emit!(MonthlyPaymentReceived {
-            vault: vault.key(),
-            month: payment.month,
-            amount: payment_amount,
+        primary_sale.purchase_price = settled_price;
+        primary_sale.discount_percentage = settled_discount;
+        primary_sale.status = PrimarySaleStatus::Settled;
+        if primary_sale.is_vesting() {
+            primary_sale.vesting_start = Clock::get()?.unix_timestamp;
+        }
+
+        emit!(TokensPurchased {
+            vault: vault_key,
+            buyer: primary_sale.buyer,
+            token_amount: primary_sale.token_amount,
+            purchase_price: primary_sale.purchase_price,
+            discount_percentage: primary_sale.discount_percentage,
         });
 
         Ok(())
     }
 
-    /// Redeem tokens for cash flow (first-come, first-served)
-    pub fn redeem_tokens(
-        ctx: Context<RedeemTokens>,
-        token_amount: u64,
-    ) -> Result<()> {
-        let vault = &ctx.accounts.vault;
-        require!(vault.is_active, MeteraError::VaultInactive);
+    /// Return an escrowed payment to the buyer after a failed offering
+    pub fn refund_primary_sale(ctx: Context<RefundPrimarySale>) -> Result<()> {
+        let offering = &ctx.accounts.primary_offering;
+        require!(offering.state == OfferingState::Failed, MeteraError::OfferingNotFailed);
 
-        // Get current month's available redemption capacity
-        let monthly_payment_record = &mut ctx.accounts.monthly_payment_record;
-        require!(monthly_payment_record.available_for_redemption >= token_amount, MeteraError::InsufficientRedemptionCapacity);
+        let primary_sale = &mut ctx.accounts.primary_sale;
+        require!(primary_sale.status == PrimarySaleStatus::Escrowed, MeteraError::SaleNotEscrowed);
 
-        // Verify user has enough tokens
-        let user_token_account = &ctx.accounts.user_token_account;
-        require!(user_token_account.amount >= token_amount, MeteraError::InsufficientTokenBalance);
+        let vault_key = offering.vault;
+        let offering_index = offering.index.to_le_bytes();
+        let seeds: &[&[u8]] = &[OFFERING_SEED, vault_key.as_ref(), &offering_index, &[offering.bump]];
 
-        // Burn tokens from user
-        token::burn(
-            CpiContext::new(
+        token::transfer(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                token::Burn {
-                    mint: ctx.accounts.token_mint.to_account_info(),
-                    from: user_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.offering_escrow.to_account_info(),
+                    to: ctx.accounts.buyer_payment_account.to_account_info(),
+                    authority: offering.to_account_info(),
                 },
+                &[seeds],
             ),
-            token_amount,
+            primary_sale.purchase_price,
         )?;
 
-        // Transfer redemption value from vault treasury to user
+        // Release the holding reserved at purchase
+        if !primary_sale.is_vesting() {
+            ctx.accounts.vault.record_outgoing(&mut ctx.accounts.buyer_holding, primary_sale.token_amount)?;
+        }
+        primary_sale.status = PrimarySaleStatus::Refunded;
+
+        emit!(PrimarySaleRefunded {
+            vault: vault_key,
+            offering: offering.key(),
+            buyer: primary_sale.buyer,
+            refund_amount: primary_sale.purchase_price,
+        });
+
+        Ok(())
+    }
+
+    /// Open a sealed-bid batch auction for `token_cap` tokens with commit and reveal phases
+    pub fn create_batch_auction(
+        ctx: Context<CreateBatchAuction>,
+        token_cap: u64,
+        commit_deadline: i64,
+        reveal_deadline: i64,
+        settlement: AuctionSettlement,
+        vesting: Option<VestingTerms>,
+    ) -> Result<()> {
+        if let Some(terms) = &vesting {
+            terms.validate()?;
+        }
+
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+        require!(token_cap > 0, MeteraError::InvalidAuctionTerms);
+        require!(token_cap <= vault.total_tokens_minted, MeteraError::ExceedsTokenSupply);
+
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(commit_deadline > current_time, MeteraError::InvalidDeadline);
+        require!(reveal_deadline > commit_deadline, MeteraError::InvalidDeadline);
+
+        let auction = &mut ctx.accounts.batch_auction;
+        auction.vault = vault.key();
+        auction.index = vault.auction_count;
+        auction.payment_mint = ctx.accounts.payment_mint.key();
+        auction.escrow = ctx.accounts.auction_escrow.key();
+        auction.token_cap = token_cap;
+        auction.commit_deadline = commit_deadline;
+        auction.reveal_deadline = reveal_deadline;
+        auction.settlement = settlement;
+        auction.vesting = vesting;
+        auction.total_revealed_demand = 0;
+        auction.demand_by_discount = [0; 101];
+        auction.is_cleared = false;
+        auction.clearing_discount_percentage = 0;
+        auction.marginal_allocation = 0;
+        auction.marginal_demand = 0;
+        auction.created_at = current_time;
+        auction.bump = ctx.bumps.batch_auction;
+
+        vault.auction_count = vault.auction_count.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(BatchAuctionCreated {
+            vault: vault.key(),
+            auction: auction.key(),
+            token_cap,
+            commit_deadline,
+            reveal_deadline,
+            settlement,
+        });
+
+        Ok(())
+    }
+
+    /// Commit a hidden bid and escrow enough payment to cover it
+    ///
+    /// `commitment` is `hashv([token_amount.to_le_bytes(), [discount_percentage], salt])`.
+    pub fn commit_sealed_bid(
+        ctx: Context<CommitSealedBid>,
+        commitment: [u8; 32],
+        escrow_amount: u64,
+    ) -> Result<()> {
+        let auction = &ctx.accounts.batch_auction;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time < auction.commit_deadline, MeteraError::AuctionCommitClosed);
+        require!(escrow_amount > 0, MeteraError::InsufficientBidEscrow);
+        ctx.accounts.investor_record.check_eligible(current_time)?;
+
         token::transfer(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.vault_treasury.to_account_info(),
-                    to: ctx.accounts.user_payment_account.to_account_info(),
-                    authority: ctx.accounts.vault_authority.to_account_info(),
+                    from: ctx.accounts.bidder_payment_account.to_account_info(),
+                    to: ctx.accounts.auction_escrow.to_account_info(),
+                    authority: ctx.accounts.bidder.to_account_info(),
                 },
             ),
-            token_amount,
+            escrow_amount,
         )?;
 
-        // Update redemption records
-        monthly_payment_record.available_for_redemption = monthly_payment_record.available_for_redemption
-            .checked_sub(token_amount)
-            .ok_or(MeteraError::ArithmeticOverflow)?;
+        let bid = &mut ctx.accounts.sealed_bid;
+        bid.auction = auction.key();
+        bid.bidder = ctx.accounts.bidder.key();
+        bid.commitment = commitment;
+        bid.escrow_amount = escrow_amount;
+        bid.token_amount = 0;
+        bid.discount_percentage = 0;
+        bid.status = SealedBidStatus::Committed;
+        bid.bump = ctx.bumps.sealed_bid;
+
+        emit!(SealedBidCommitted {
+            auction: auction.key(),
+            bidder: bid.bidder,
+            escrow_amount,
+        });
 
-        let vault_mut = &mut ctx.accounts.vault_mut;
-        vault_mut.total_redeemed = vault_mut.total_redeemed
-            .checked_add(token_amount)
-            .ok_or(MeteraError::ArithmeticOverflow)?;
+        Ok(())
+    }
 
-        // Record redemption
-        let redemption = &mut ctx.accounts.redemption_record;
-        redemption.vault = vault.key();
-        redemption.redeemer = ctx.accounts.user.key();
-        redemption.token_amount = token_amount;
-        redemption.redemption_value = token_amount;
-        redemption.redeemed_at = Clock::get()?.unix_timestamp;
-        redemption.month = monthly_payment_record.month;
+    /// Reveal a committed bid during the reveal phase
+    pub fn reveal_sealed_bid(
+        ctx: Context<RevealSealedBid>,
+        token_amount: u64,
+        discount_percentage: u8,
+        salt: [u8; 32],
+    ) -> Result<()> {
+        require!(discount_percentage <= 100, MeteraError::InvalidDiscount);
+        require!(token_amount > 0, MeteraError::InvalidBidReveal);
 
-        emit!(TokensRedeemed {
-            vault: vault.key(),
-            redeemer: ctx.accounts.user.key(),
+        let auction = &mut ctx.accounts.batch_auction;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(
+            current_time >= auction.commit_deadline && current_time < auction.reveal_deadline,
+            MeteraError::AuctionNotInReveal
+        );
+
+        let bid = &mut ctx.accounts.sealed_bid;
+        require!(bid.status == SealedBidStatus::Committed, MeteraError::BidAlreadyRevealed);
+
+        let hash = anchor_lang::solana_program::hash::hashv(&[
+            &token_amount.to_le_bytes(),
+            &[discount_percentage],
+            &salt,
+        ]);
+        require!(hash.to_bytes() == bid.commitment, MeteraError::InvalidBidReveal);
+        require!(
+            discounted_price(token_amount, discount_percentage)? <= bid.escrow_amount,
+            MeteraError::InsufficientBidEscrow
+        );
+
+        let demand = &mut auction.demand_by_discount[discount_percentage as usize];
+        *demand = demand.checked_add(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        auction.total_revealed_demand = auction.total_revealed_demand.checked_add(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        bid.token_amount = token_amount;
+        bid.discount_percentage = discount_percentage;
+        bid.status = SealedBidStatus::Revealed;
+
+        emit!(SealedBidRevealed {
+            auction: auction.key(),
+            bidder: bid.bidder,
             token_amount,
-            redemption_value: token_amount,
-            month: monthly_payment_record.month,
+            discount_percentage,
         });
 
         Ok(())
     }
 
-    // ============ LIQUIDITY PROVISION MODULE ============
+    /// Allocate the cap to revealed bids from the lowest discount upward
+    pub fn clear_batch_auction(ctx: Context<ClearBatchAuction>) -> Result<()> {
+        let auction = &mut ctx.accounts.batch_auction;
+        require!(!auction.is_cleared, MeteraError::AuctionAlreadyCleared);
+        require!(Clock::get()?.unix_timestamp >= auction.reveal_deadline, MeteraError::AuctionRevealOpen);
+
+        // Bids below the clearing discount fill in full; bids at it share what is left pro rata
+        let demand_by_discount = auction.demand_by_discount;
+        let mut filled: u64 = 0;
+        for (discount, demand) in demand_by_discount.into_iter().enumerate() {
+            if demand == 0 {
+                continue;
+            }
+
+            let remaining = auction.token_cap.checked_sub(filled).ok_or(MeteraError::ArithmeticOverflow)?;
+            auction.clearing_discount_percentage = discount as u8;
+            auction.marginal_demand = demand;
+            auction.marginal_allocation = remaining.min(demand);
+            filled = filled.checked_add(auction.marginal_allocation).ok_or(MeteraError::ArithmeticOverflow)?;
+
+            if filled == auction.token_cap {
+                break;
+            }
+        }
 
-    /// Create a liquidity pool for secondary market
-    pub fn create_liquidity_pool(
-        ctx: Context<CreateLiquidityPool>,
-        pool_name: String,
-    ) -> Result<()> {
-        let pool = &mut ctx.accounts.liquidity_pool;
-        pool.vault = ctx.accounts.vault.key();
-        pool.token_a_mint = ctx.accounts.token_a_mint.key();
-        pool.token_b_mint = ctx.accounts.token_b_mint.key();
-        pool.pool_authority = ctx.accounts.authority.key();
-        pool.pool_name = pool_name;
-        pool.created_at = Clock::get()?.unix_timestamp;
-        pool.token_a_reserve = 0;
-        pool.token_b_reserve = 0;
-        pool.total_lp_shares = 0;
-        pool.window_start = Clock::get()?.unix_timestamp;
-        pool.window_number = 0;
-        pool.is_active = true;
+        auction.is_cleared = true;
 
-        emit!(LiquidityPoolCreated {
-            pool: pool.key(),
-            vault: ctx.accounts.vault.key(),
-            token_a: ctx.accounts.token_a_mint.key(),
-            token_b: ctx.accounts.token_b_mint.key(),
-            pool_name: pool.pool_name.clone(),
+        emit!(BatchAuctionCleared {
+            vault: auction.vault,
+            auction: auction.key(),
+            clearing_discount_percentage: auction.clearing_discount_percentage,
+            total_revealed_demand: auction.total_revealed_demand,
+            tokens_allocated: filled,
         });
 
         Ok(())
     }
 
-    /// Provide liquidity to the pool
-    pub fn provide_liquidity(
-        ctx: Context<ProvideLiquidity>,
-        token_a_amount: u64,
-        token_b_amount: u64,
-        forward_discount_percentage: u8,
-    ) -> Result<()> {
-        require!(forward_discount_percentage <= 100, MeteraError::InvalidDiscount);
+    /// Settle a winning bid: pay the treasury, refund the rest of the escrow and mint the allocation
+    pub fn settle_sealed_bid(ctx: Context<SettleSealedBid>) -> Result<()> {
+        let auction = &ctx.accounts.batch_auction;
+        require!(auction.is_cleared, MeteraError::AuctionNotCleared);
+
+        let bid = &mut ctx.accounts.sealed_bid;
+        require!(bid.status == SealedBidStatus::Revealed, MeteraError::BidAlreadySettled);
+
+        let allocation = auction.allocation_for(bid)?;
+        require!(allocation > 0, MeteraError::BidNotWinning);
+
+        // Vesting auctions mint into the vesting escrow; the holding limits apply as tokens are claimed
+        let bidder_token_account = &ctx.accounts.bidder_token_account;
+        if auction.vesting.is_some() {
+            require_keys_eq!(
+                bidder_token_account.key(),
+                vesting_escrow_address(&auction.vault),
+                MeteraError::InvalidVestingEscrow
+            );
+        } else {
+            require_keys_eq!(bidder_token_account.owner, bid.bidder, MeteraError::InvalidTokenAccountOwner);
+            ctx.accounts.vault.record_incoming(&mut ctx.accounts.bidder_holding, allocation)?;
+        }
 
-        let pool = &mut ctx.accounts.liquidity_pool;
-        require!(pool.is_active, MeteraError::PoolInactive);
+        let settled_discount = match auction.settlement {
+            AuctionSettlement::PayAsBid => bid.discount_percentage,
+            AuctionSettlement::UniformClearing => auction.clearing_discount_percentage,
+        };
+        let purchase_price = discounted_price(allocation, settled_discount)?;
+        let refund_amount = bid.escrow_amount.checked_sub(purchase_price).ok_or(MeteraError::ArithmeticOverflow)?;
 
-        // Check if 3-month window needs reset
-        let current_time = Clock::get()?.unix_timestamp;
-        let window_duration = 90 * 24 * 60 * 60; // 3 months in seconds
-        if current_time.checked_sub(pool.window_start).ok_or(MeteraError::ArithmeticOverflow)? > window_duration {
-            pool.window_start = current_time;
-            pool.window_number = pool.window_number.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
-        }
+        let vault_key = auction.vault;
+        let auction_index = auction.index.to_le_bytes();
+        let seeds: &[&[u8]] = &[BATCH_AUCTION_SEED, vault_key.as_ref(), &auction_index, &[auction.bump]];
+
+        let fee = FeeCharge::new(
+            FeeKind::Origination,
+            purchase_price,
+            ctx.accounts.protocol_config.protocol_fees.origination_fee_bps,
+            ctx.accounts.vault.authority_fees.origination_fee_bps,
+        )?;
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.auction_escrow.to_account_info(),
+            auction.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault_key,
+            &fee,
+            &[seeds],
+        )?;
 
-        // Transfer tokens from LP to pool
         token::transfer(
-            CpiContext::new(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.lp_token_a_account.to_account_info(),
-                    to: ctx.accounts.pool_token_a_vault.to_account_info(),
-                    authority: ctx.accounts.lp.to_account_info(),
+                    from: ctx.accounts.auction_escrow.to_account_info(),
+                    to: ctx.accounts.vault_treasury.to_account_info(),
+                    authority: auction.to_account_info(),
                 },
+                &[seeds],
             ),
-            token_a_amount,
+            fee.net()?,
         )?;
 
-        token::transfer(
+        if refund_amount > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.auction_escrow.to_account_info(),
+                        to: ctx.accounts.bidder_payment_account.to_account_info(),
+                        authority: auction.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                refund_amount,
+            )?;
+        }
+
+        token_interface::mint_to(
             CpiContext::new(
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::MintTo {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.bidder_token_account.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            allocation,
+        )?;
+
+        bid.status = SealedBidStatus::Settled;
+
+        // Record purchase
+        let current_time = Clock::get()?.unix_timestamp;
+        let primary_sale = &mut ctx.accounts.primary_sale;
+        primary_sale.vault = vault_key;
+        primary_sale.buyer = bid.bidder;
+        primary_sale.token_amount = allocation;
+        primary_sale.purchase_price = purchase_price;
+        primary_sale.discount_percentage = settled_discount;
+        primary_sale.purchased_at = current_time;
+        primary_sale.offering = auction.key();
+        primary_sale.status = PrimarySaleStatus::Settled;
+        primary_sale.set_vesting(auction.vesting, current_time);
+
+        emit!(TokensPurchased {
+            vault: vault_key,
+            buyer: bid.bidder,
+            token_amount: allocation,
+            purchase_price,
+            discount_percentage: settled_discount,
+        });
+
+        Ok(())
+    }
+
+    /// Return the full escrow of a losing or unrevealed bid once the auction has cleared
+    pub fn reclaim_sealed_bid(ctx: Context<ReclaimSealedBid>) -> Result<()> {
+        let auction = &ctx.accounts.batch_auction;
+        require!(auction.is_cleared, MeteraError::AuctionNotCleared);
+
+        let bid = &mut ctx.accounts.sealed_bid;
+        require!(
+            bid.status == SealedBidStatus::Committed || bid.status == SealedBidStatus::Revealed,
+            MeteraError::BidAlreadySettled
+        );
+        require!(auction.allocation_for(bid)? == 0, MeteraError::BidWinning);
+
+        let vault_key = auction.vault;
+        let auction_index = auction.index.to_le_bytes();
+        let seeds: &[&[u8]] = &[BATCH_AUCTION_SEED, vault_key.as_ref(), &auction_index, &[auction.bump]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.lp_token_b_account.to_account_info(),
-                    to: ctx.accounts.pool_token_b_vault.to_account_info(),
-                    authority: ctx.accounts.lp.to_account_info(),
+                    from: ctx.accounts.auction_escrow.to_account_info(),
+                    to: ctx.accounts.bidder_payment_account.to_account_info(),
+                    authority: auction.to_account_info(),
                 },
+                &[seeds],
             ),
-            token_b_amount,
+            bid.escrow_amount,
         )?;
 
-        // Calculate and mint LP shares
-        let lp_shares = if pool.total_lp_shares == 0 {
-            // Initial liquidity
-            (token_a_amount as u128)
-                .checked_mul(token_b_amount as u128)
-                .ok_or(MeteraError::ArithmeticOverflow)?
-                .isqrt() as u64
-        } else {
-            // Proportional shares
-            let a_ratio = (token_a_amount as u128)
-                .checked_mul(pool.total_lp_shares as u128)
-                .ok_or(MeteraError::ArithmeticOverflow)?
-                .checked_div(pool.token_a_reserve as u128)
-                .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        bid.status = SealedBidStatus::Refunded;
 
-            let b_ratio = (token_b_amount as u128)
-                .checked_mul(pool.total_lp_shares as u128)
-                .ok_or(MeteraError::ArithmeticOverflow)?
-                .checked_div(pool.token_b_reserve as u128)
-                .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        emit!(SealedBidReclaimed {
+            auction: auction.key(),
+            bidder: bid.bidder,
+            refund_amount: bid.escrow_amount,
+        });
 
-            a_ratio.min(b_ratio)
-        };
+        Ok(())
+    }
 
-        pool.token_a_reserve = pool.token_a_reserve.checked_add(token_a_amount).ok_or(MeteraError::ArithmeticOverflow)?;
-        pool.token_b_reserve = pool.token_b_reserve.checked_add(token_b_amount).ok_or(MeteraError::ArithmeticOverflow)?;
-        pool.total_lp_shares = pool.total_lp_shares.checked_add(lp_shares).ok_or(MeteraError::ArithmeticOverflow)?;
+    /// Create the vault's vesting escrow that holds tokens from vesting primary purchases
+    pub fn create_vesting_escrow(ctx: Context<CreateVestingEscrow>) -> Result<()> {
+        emit!(VestingEscrowCreated {
+            vault: ctx.accounts.vault.key(),
+            vesting_escrow: ctx.accounts.vesting_escrow.key(),
+        });
 
-        // Record LP position
-        let lp_position = &mut ctx.accounts.lp_position;
-        lp_position.pool = pool.key();
-        lp_position.lp = ctx.accounts.lp.key();
-        lp_position.token_a_amount = token_a_amount;
-        lp_position.token_b_amount = token_b_amount;
-        lp_position.lp_shares = lp_shares;
-        lp_position.forward_discount_percentage = forward_discount_percentage;
-        lp_position.window_number = pool.window_number;
-        lp_position.provided_at = current_time;
+        Ok(())
+    }
 
-        emit!(LiquidityProvided {
-            pool: pool.key(),
-            lp: ctx.accounts.lp.key(),
-            token_a_amount,
-            token_b_amount,
-            lp_shares,
-            forward_discount_percentage,
+    /// Release the vested portion of a primary purchase to the buyer
+    pub fn claim_vested_tokens<'info>(ctx: Context<'_, '_, '_, 'info, ClaimVestedTokens<'info>>) -> Result<()> {
+        let primary_sale = &mut ctx.accounts.primary_sale;
+        require!(primary_sale.is_vesting(), MeteraError::SaleNotVesting);
+        require!(primary_sale.status == PrimarySaleStatus::Settled, MeteraError::SaleNotSettled);
+
+        let vested = primary_sale.vested_amount(Clock::get()?.unix_timestamp)?;
+        let claimable = vested.checked_sub(primary_sale.released_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        require!(claimable > 0, MeteraError::NothingVested);
+        ctx.accounts.vault.record_incoming(&mut ctx.accounts.buyer_holding, claimable)?;
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VESTING_AUTHORITY_SEED, vault_key.as_ref(), &[ctx.bumps.vesting_authority]];
+
+        transfer_checked_with_hook(
+            &ctx.accounts.token_program,
+            &ctx.accounts.vesting_escrow,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.buyer_token_account,
+            ctx.accounts.vesting_authority.to_account_info(),
+            ctx.remaining_accounts,
+            claimable,
+            &[seeds],
+        )?;
+
+        primary_sale.released_amount = vested;
+
+        emit!(VestedTokensClaimed {
+            vault: vault_key,
+            buyer: primary_sale.buyer,
+            primary_sale: primary_sale.key(),
+            amount: claimable,
+            total_released: vested,
         });
 
         Ok(())
     }
 
-    /// Withdraw liquidity from the pool
-    pub fn withdraw_liquidity(ctx: Context<WithdrawLiquidity>, lp_shares: u64) -> Result<()> {
-        let pool = &mut ctx.accounts.liquidity_pool;
-        require!(pool.is_active, MeteraError::PoolInactive);
-        require!(pool.total_lp_shares > 0, MeteraError::ZeroLiquidityPool);
+    // ============ MONTHLY REPAYMENT CYCLE ============
 
-        let lp_position = &mut ctx.accounts.lp_position;
-        require!(lp_position.lp_shares >= lp_shares, MeteraError::InsufficientLPShares);
+    /// Receive monthly payment into the vault
+    pub fn receive_monthly_payment(ctx: Context<ReceiveMonthlyPayment>, payment_amount: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+        require!(vault.current_month < vault.total_months, MeteraError::VaultMatured);
 
-        // Calculate proportional amounts to withdraw
-        let token_a_amount = (lp_shares as u128)
-            .checked_mul(pool.token_a_reserve as u128)
-            .ok_or(MeteraError::ArithmeticOverflow)?
-            .checked_div(pool.total_lp_shares as u128)
-            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        // Verify payment matches expected monthly payment
+        require!(payment_amount == vault.monthly_payment, MeteraError::InvalidPaymentAmount);
 
-        let token_b_amount = (lp_shares as u128)
-            .checked_mul(pool.token_b_reserve as u128)
-            .ok_or(MeteraError::ArithmeticOverflow)?
-            .checked_div(pool.total_lp_shares as u128)
-            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        // Servicing fee is skimmed before the payment reaches the treasury
+        let fee = FeeCharge::new(
+            FeeKind::Servicing,
+            payment_amount,
+            ctx.accounts.protocol_config.protocol_fees.servicing_fee_bps,
+            vault.authority_fees.servicing_fee_bps,
+        )?;
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.payer_account.to_account_info(),
+            ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault.key(),
+            &fee,
+            &[],
+        )?;
+        let net_payment = fee.net()?;
 
-        // Transfer tokens from pool to LP
+        // Transfer payment to vault treasury
         token::transfer(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.pool_token_a_vault.to_account_info(),
-                    to: ctx.accounts.lp_token_a_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    from: ctx.accounts.payer_account.to_account_info(),
+                    to: ctx.accounts.vault_treasury.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
                 },
             ),
-            token_a_amount,
+            net_payment,
+        )?;
+
+        // Record payment
+        let payment = &mut ctx.accounts.monthly_payment_record;
+        payment.vault = vault.key();
+        payment.month = vault.current_month;
+        payment.amount = payment_amount;
+        payment.received_at = Clock::get()?.unix_timestamp;
+        payment.available_for_redemption = net_payment;
+        payment.token_supply = ctx.accounts.token_mint.supply;
+
+        vault.current_month = vault.current_month.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(MonthlyPaymentReceived {
+            vault: vault.key(),
+            month: payment.month,
+            amount: payment_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Redeem tokens for cash flow (first-come, first-served)
+    pub fn redeem_tokens(
+        ctx: Context<RedeemTokens>,
+        token_amount: u64,
+    ) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+        ctx.accounts.investor_record.check_eligible(Clock::get()?.unix_timestamp)?;
+
+        // Get current month's available redemption capacity
+        let monthly_payment_record = &mut ctx.accounts.monthly_payment_record;
+        require!(monthly_payment_record.available_for_redemption >= token_amount, MeteraError::InsufficientRedemptionCapacity);
+
+        // Verify user has enough tokens
+        let user_token_account = &ctx.accounts.user_token_account;
+        require!(user_token_account.amount >= token_amount, MeteraError::InsufficientTokenBalance);
+
+        // Burn tokens from user
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: user_token_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            token_amount,
+        )?;
+
+        // Redemption fee is withheld from the payout
+        let fee = FeeCharge::new(
+            FeeKind::Redemption,
+            token_amount,
+            ctx.accounts.protocol_config.protocol_fees.redemption_fee_bps,
+            vault.authority_fees.redemption_fee_bps,
         )?;
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.vault_treasury.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault.key(),
+            &fee,
+            &[],
+        )?;
+        let redemption_value = fee.net()?;
 
+        // Transfer redemption value from vault treasury to user
         token::transfer(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.pool_token_b_vault.to_account_info(),
-                    to: ctx.accounts.lp_token_b_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    from: ctx.accounts.vault_treasury.to_account_info(),
+                    to: ctx.accounts.user_payment_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
                 },
             ),
-            token_b_amount,
+            redemption_value,
         )?;
 
-        // Update pool and position
-        pool.token_a_reserve = pool.token_a_reserve.checked_sub(token_a_amount).ok_or(MeteraError::ArithmeticOverflow)?;
-        pool.token_b_reserve = pool.token_b_reserve.checked_sub(token_b_amount).ok_or(MeteraError::ArithmeticOverflow)?;
-        pool.total_lp_shares = pool.total_lp_shares.checked_sub(lp_shares).ok_or(MeteraError::ArithmeticOverflow)?;
-        lp_position.lp_shares = lp_position.lp_shares.checked_sub(lp_shares).ok_or(MeteraError::ArithmeticOverflow)?;
+        // Update redemption records
+        monthly_payment_record.available_for_redemption = monthly_payment_record.available_for_redemption
+            .checked_sub(token_amount)
+            .ok_or(MeteraError::ArithmeticOverflow)?;
 
-        emit!(LiquidityWithdrawn {
-            pool: pool.key(),
-            lp: ctx.accounts.lp.key(),
-            lp_shares,
-            token_a_amount,
-            token_b_amount,
+        let vault_mut = &mut ctx.accounts.vault_mut;
+        vault_mut.total_redeemed = vault_mut.total_redeemed
+            .checked_add(token_amount)
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        vault_mut.record_outgoing(&mut ctx.accounts.user_holding, token_amount)?;
+
+        // Record redemption
+        let redemption = &mut ctx.accounts.redemption_record;
+        redemption.vault = vault.key();
+        redemption.redeemer = ctx.accounts.user.key();
+        redemption.token_amount = token_amount;
+        redemption.redemption_value = redemption_value;
+        redemption.redeemed_at = Clock::get()?.unix_timestamp;
+        redemption.month = monthly_payment_record.month;
+
+        emit!(TokensRedeemed {
+            vault: vault.key(),
+            redeemer: ctx.accounts.user.key(),
+            token_amount,
+            redemption_value,
+            month: monthly_payment_record.month,
         });
 
         Ok(())
     }
-}
 
-// ============ ACCOUNTS & STRUCTS ============
+    // ============ LIQUIDITY PROVISION MODULE ============
 
-#[derive(Accounts)]
-#[instruction(principal: u64, total_expected_interest: u64, monthly_payment: u64, total_months: u32, vault_name: String)]
-pub struct CreateVault<'info> {
-    #[account(init, payer = authority, space = Vault::INIT_SPACE)]
-    pub vault: Account<'info, Vault>,
+    /// Create a liquidity pool for secondary market
+    pub fn create_liquidity_pool(
+        ctx: Context<CreateLiquidityPool>,
+        pool_name: String,
+        lp_fee_bps: u16,
+        curve: CurveType,
+        amp: u64,
+    ) -> Result<()> {
+        require!(lp_fee_bps <= MAX_FEE_BPS, MeteraError::InvalidFeeSchedule);
+
+        // StableSwap prices raw amounts near 1:1, so both sides must share decimals
+        let amp = match curve {
+            CurveType::ConstantProduct => 0,
+            CurveType::StableSwap => {
+                require!((MIN_AMP..=MAX_AMP).contains(&amp), MeteraError::InvalidAmplification);
+                require!(
+                    ctx.accounts.token_a_mint.decimals == ctx.accounts.token_b_mint.decimals,
+                    MeteraError::InvalidCurve
+                );
+                amp
+            }
+        };
 
-    #[account(init, payer = authority, mint::decimals = 6, mint::authority = authority)]
-    pub token_mint: Account<'info, Mint>,
+        let pool = &mut ctx.accounts.liquidity_pool;
+        pool.vault = ctx.accounts.vault.key();
+        pool.token_a_mint = ctx.accounts.token_a_mint.key();
+        pool.token_b_mint = ctx.accounts.token_b_mint.key();
+        pool.pool_authority = ctx.accounts.authority.key();
+        pool.pool_name = pool_name;
+        pool.created_at = Clock::get()?.unix_timestamp;
+        pool.token_a_reserve = 0;
+        pool.token_b_reserve = 0;
+        pool.total_lp_shares = 0;
+        pool.window_start = Clock::get()?.unix_timestamp;
+        pool.window_number = 0;
+        pool.is_active = true;
+        pool.lp_fee_bps = lp_fee_bps;
+        pool.fee_growth_a = 0;
+        pool.fee_growth_b = 0;
+        pool.lp_mint = ctx.accounts.lp_mint.key();
+        pool.lp_mint_bump = ctx.bumps.lp_mint;
+        pool.curve = curve;
+        pool.amp_initial = amp;
+        pool.amp_target = amp;
+        pool.amp_ramp_start = pool.created_at;
+        pool.amp_ramp_end = pool.created_at;
+        pool.price_a_cumulative = 0;
+        pool.price_b_cumulative = 0;
+        pool.last_price_update = pool.created_at;
+        pool.flash_loan_amount = 0;
+        pool.flash_loan_token_a = false;
 
-    #[account(init, payer = authority, token::mint = token_mint, token::authority = vault)]
-    pub vault_treasury: Account<'info, TokenAccount>,
+        emit!(LiquidityPoolCreated {
+            pool: pool.key(),
+            vault: ctx.accounts.vault.key(),
+            token_a: ctx.accounts.token_a_mint.key(),
+            token_b: ctx.accounts.token_b_mint.key(),
+            pool_name: pool.pool_name.clone(),
+            lp_mint: pool.lp_mint,
+            curve,
+            amp,
+        });
 
-    pub authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct MintTokens<'info> {
-    #[account(mut)]
-    pub vault: Account<'info, Vault>,
+    /// Provide liquidity to the pool; the amounts are maxima and only the pool's current ratio is taken
+    pub fn provide_liquidity<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProvideLiquidity<'info>>,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        forward_discount_percentage: u8,
+        window_number: u64,
+        min_lp_shares: u64,
+    ) -> Result<()> {
+        require!(forward_discount_percentage <= 100, MeteraError::InvalidDiscount);
 
-    #[account(mut)]
-    pub token_mint: Account<'info, Mint>,
+        let pool = &mut ctx.accounts.liquidity_pool;
+        require!(pool.is_active, MeteraError::PoolInactive);
 
-    #[account(mut)]
-    pub destination: Account<'info, TokenAccount>,
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.investor_record.check_eligible(current_time)?;
 
-    pub authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+        // The position PDA is keyed by the window the deposit lands in
+        require!(window_number == pool.sync_window(current_time)?, MeteraError::WindowMismatch);
 
-#[derive(Accounts)]
-pub struct PurchaseTokensPrimary<'info> {
-    #[account(mut)]
-    pub vault: Account<'info, Vault>,
+        pool.accumulate_prices(current_time)?;
+        ctx.accounts.price_observations.record(pool, current_time)?;
 
-    #[account(mut)]
-    pub token_mint: Account<'info, Mint>,
+        // The excess of either token stays with the LP
+        let (token_a_amount, token_b_amount, minted_shares) = pool.deposit_quote(token_a_amount, token_b_amount, current_time)?;
+        let lp_shares = if pool.total_lp_shares == 0 {
+            minted_shares.checked_sub(MINIMUM_LIQUIDITY).ok_or(MeteraError::ArithmeticOverflow)?
+        } else {
+            minted_shares
+        };
+        require!(lp_shares > 0, MeteraError::InsufficientLiquidity);
+        require!(lp_shares >= min_lp_shares, MeteraError::SlippageExceeded);
 
-    #[account(mut)]
-    pub vault_treasury: Account<'info, TokenAccount>,
+        // Only the vault token side of the pool counts towards holdings
+        let vault = &mut ctx.accounts.vault;
+        if pool.token_a_mint == vault.token_mint {
+            vault.record_outgoing(&mut ctx.accounts.lp_holding, token_a_amount)?;
+        }
+        if pool.token_b_mint == vault.token_mint {
+            vault.record_outgoing(&mut ctx.accounts.lp_holding, token_b_amount)?;
+        }
 
-    #[account(init, payer = buyer, space = PrimarySale::INIT_SPACE)]
-    pub primary_sale: Account<'info, PrimarySale>,
+        // Transfer tokens from LP to pool
+        transfer_checked_with_hook(
+            &ctx.accounts.token_a_program,
+            &ctx.accounts.lp_token_a_account,
+            &ctx.accounts.token_a_mint,
+            &ctx.accounts.pool_token_a_vault,
+            ctx.accounts.lp.to_account_info(),
+            ctx.remaining_accounts,
+            token_a_amount,
+            &[],
+        )?;
 
-    #[account(mut)]
-    pub buyer_payment_account: Account<'info, TokenAccount>,
+        transfer_checked_with_hook(
+            &ctx.accounts.token_b_program,
+            &ctx.accounts.lp_token_b_account,
+            &ctx.accounts.token_b_mint,
+            &ctx.accounts.pool_token_b_vault,
+            ctx.accounts.lp.to_account_info(),
+            ctx.remaining_accounts,
+            token_b_amount,
+            &[],
+        )?;
 
-    #[account(mut)]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+        // On the first deposit MINIMUM_LIQUIDITY shares are counted but never minted
+        pool.token_a_reserve = pool.token_a_reserve.checked_add(token_a_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        pool.token_b_reserve = pool.token_b_reserve.checked_add(token_b_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        pool.total_lp_shares = pool.total_lp_shares.checked_add(minted_shares).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let pool_key = pool.key();
+        let seeds: &[&[u8]] = &[LP_MINT_SEED, pool_key.as_ref(), &[pool.lp_mint_bump]];
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::MintTo {
+                    mint: ctx.accounts.lp_mint.to_account_info(),
+                    to: ctx.accounts.lp_share_escrow.to_account_info(),
+                    authority: ctx.accounts.lp_mint.to_account_info(),
+                },
+                &[seeds],
+            ),
+            lp_shares,
+        )?;
+
+        // The shares stay in the position's escrow until its window ends, which is what commits the liquidity
+        let lp_position = &mut ctx.accounts.lp_position;
+        lp_position.open_if_new(pool, pool_key, ctx.accounts.lp.key(), window_number, current_time, ctx.bumps.lp_position)?;
+
+        // Settle fees on the existing shares before the share count changes
+        lp_position.accrue_fees(pool.fee_growth_a, pool.fee_growth_b)?;
+        lp_position.add_deposit(token_a_amount, token_b_amount, lp_shares, forward_discount_percentage)?;
+
+        emit!(LiquidityProvided {
+            pool: pool.key(),
+            lp: ctx.accounts.lp.key(),
+            token_a_amount,
+            token_b_amount,
+            lp_shares,
+            forward_discount_percentage,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw liquidity from the pool
+    pub fn withdraw_liquidity<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawLiquidity<'info>>,
+        lp_shares: u64,
+        min_token_a: u64,
+        min_token_b: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.liquidity_pool;
+        require!(pool.is_active, MeteraError::PoolInactive);
+        require!(pool.total_lp_shares > 0, MeteraError::ZeroLiquidityPool);
+
+        require!(ctx.accounts.lp_share_account.amount >= lp_shares, MeteraError::InsufficientLPShares);
+
+        // A position's shares sit in its escrow until the window closes; shares in the LP's own account are free to exit
+        let current_time = Clock::get()?.unix_timestamp;
+        match ctx.accounts.lp_position.as_mut() {
+            Some(lp_position) => {
+                require_keys_eq!(ctx.accounts.lp_share_account.owner, lp_position.key(), MeteraError::InvalidLpPosition);
+                require!(current_time >= pool.window_end(lp_position.window_number)?, MeteraError::LiquidityLocked);
+
+                // Fees earned on the withdrawn shares stay claimable
+                lp_position.accrue_fees(pool.fee_growth_a, pool.fee_growth_b)?;
+            }
+            None => require_keys_eq!(
+                ctx.accounts.lp_share_account.owner,
+                ctx.accounts.lp.key(),
+                MeteraError::InvalidTokenAccountOwner
+            ),
+        }
+        pool.accumulate_prices(current_time)?;
+
+        // Proportional withdrawal keeps the invariant per share unchanged under either curve
+        let token_a_amount = (lp_shares as u128)
+            .checked_mul(pool.token_a_reserve as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(pool.total_lp_shares as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+
+        let token_b_amount = (lp_shares as u128)
+            .checked_mul(pool.token_b_reserve as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(pool.total_lp_shares as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        require!(token_a_amount >= min_token_a && token_b_amount >= min_token_b, MeteraError::SlippageExceeded);
+
+        let vault = &mut ctx.accounts.vault;
+        if pool.token_a_mint == vault.token_mint {
+            vault.record_incoming(&mut ctx.accounts.lp_holding, token_a_amount)?;
+        }
+        if pool.token_b_mint == vault.token_mint {
+            vault.record_incoming(&mut ctx.accounts.lp_holding, token_b_amount)?;
+        }
+
+        // Transfer tokens from pool to LP
+        transfer_checked_with_hook(
+            &ctx.accounts.token_a_program,
+            &ctx.accounts.pool_token_a_vault,
+            &ctx.accounts.token_a_mint,
+            &ctx.accounts.lp_token_a_account,
+            ctx.accounts.pool_authority.to_account_info(),
+            ctx.remaining_accounts,
+            token_a_amount,
+            &[],
+        )?;
+
+        transfer_checked_with_hook(
+            &ctx.accounts.token_b_program,
+            &ctx.accounts.pool_token_b_vault,
+            &ctx.accounts.token_b_mint,
+            &ctx.accounts.lp_token_b_account,
+            ctx.accounts.pool_authority.to_account_info(),
+            ctx.remaining_accounts,
+            token_b_amount,
+            &[],
+        )?;
+
+        let pool_key = pool.key();
+        let lp_key = ctx.accounts.lp.key();
+        match ctx.accounts.lp_position.as_mut() {
+            Some(lp_position) => {
+                let window = lp_position.window_number.to_le_bytes();
+                let seeds: &[&[u8]] = &[LP_POSITION_SEED, pool_key.as_ref(), lp_key.as_ref(), &window, &[lp_position.bump]];
+                token::burn(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        token::Burn {
+                            mint: ctx.accounts.lp_mint.to_account_info(),
+                            from: ctx.accounts.lp_share_account.to_account_info(),
+                            authority: lp_position.to_account_info(),
+                        },
+                        &[seeds],
+                    ),
+                    lp_shares,
+                )?;
+                lp_position.remove_shares(lp_shares)?;
+            }
+            None => token::burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Burn {
+                        mint: ctx.accounts.lp_mint.to_account_info(),
+                        from: ctx.accounts.lp_share_account.to_account_info(),
+                        authority: ctx.accounts.lp.to_account_info(),
+                    },
+                ),
+                lp_shares,
+            )?,
+        }
+
+        // Update pool
+        pool.token_a_reserve = pool.token_a_reserve.checked_sub(token_a_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        pool.token_b_reserve = pool.token_b_reserve.checked_sub(token_b_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        pool.total_lp_shares = pool.total_lp_shares.checked_sub(lp_shares).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(LiquidityWithdrawn {
+            pool: pool.key(),
+            lp: ctx.accounts.lp.key(),
+            lp_shares,
+            token_a_amount,
+            token_b_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Swap along the pool's curve
+    pub fn swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        a_to_b: bool,
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.investor_record.check_eligible(current_time)?;
+
+        let accounts = &mut *ctx.accounts;
+        let (in_program, in_mint, user_in, pool_in, out_program, out_mint, user_out, pool_out) = if a_to_b {
+            (
+                &accounts.token_a_program,
+                &accounts.token_a_mint,
+                &accounts.user_token_a_account,
+                &accounts.pool_token_a_vault,
+                &accounts.token_b_program,
+                &accounts.token_b_mint,
+                &accounts.user_token_b_account,
+                &accounts.pool_token_b_vault,
+            )
+        } else {
+            (
+                &accounts.token_b_program,
+                &accounts.token_b_mint,
+                &accounts.user_token_b_account,
+                &accounts.pool_token_b_vault,
+                &accounts.token_a_program,
+                &accounts.token_a_mint,
+                &accounts.user_token_a_account,
+                &accounts.pool_token_a_vault,
+            )
+        };
+
+        let (amount_out, lp_fee) = SwapLeg {
+            pool: &mut accounts.liquidity_pool,
+            vault: &mut accounts.vault,
+            holding: &mut accounts.user_holding,
+            in_program,
+            in_mint,
+            user_in,
+            pool_in,
+            out_program,
+            out_mint,
+            user_out,
+            pool_out,
+            protocol_fee_account: &accounts.protocol_fee_account,
+            authority_fee_account: &accounts.authority_fee_account,
+            user: accounts.user.to_account_info(),
+            pool_authority: accounts.pool_authority.to_account_info(),
+        }
+        .execute(amount_in, accounts.protocol_config.protocol_fees.swap_fee_bps, ctx.remaining_accounts, current_time)?;
+        require!(amount_out >= minimum_amount_out, MeteraError::SlippageExceeded);
+
+        accounts.price_observations.record(&accounts.liquidity_pool, current_time)?;
+
+        emit!(TokensSwapped {
+            pool: accounts.liquidity_pool.key(),
+            user: accounts.user.key(),
+            amount_in,
+            amount_out,
+            a_to_b,
+            lp_fee,
+        });
+
+        Ok(())
+    }
+
+    /// Swap through a path of pools in one instruction, checking only the final amount out.
+    /// `remaining_accounts` holds `ROUTE_HOP_ACCOUNTS` accounts per hop (pool, vault, pool authority, input and
+    /// output mints, pool input and output vaults, protocol and authority fee accounts, the user's output account,
+    /// input and output token programs, the user's holding for the hop's vault, the pool's price observations), followed by any transfer-hook
+    /// accounts; every hop's pool authority must sign.
+    pub fn route_swap<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RouteSwap<'info>>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        hops: u8,
+    ) -> Result<()> {
+        let hops = hops as usize;
+        require!(
+            (1..=MAX_ROUTE_HOPS).contains(&hops) && ctx.remaining_accounts.len() >= hops * ROUTE_HOP_ACCOUNTS,
+            MeteraError::InvalidRoute
+        );
+
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.investor_record.check_eligible(current_time)?;
+
+        let user_key = ctx.accounts.user.key();
+        let swap_fee_bps = ctx.accounts.protocol_config.protocol_fees.swap_fee_bps;
+        let mut pools = Vec::with_capacity(hops);
+        let mut hop_amount = amount_in;
+        let mut previous_out: Option<&'info AccountInfo<'info>> = None;
+        let mut destination_mint = ctx.accounts.user_source.mint;
+
+        for hop in ctx.remaining_accounts[..hops * ROUTE_HOP_ACCOUNTS].chunks(ROUTE_HOP_ACCOUNTS) {
+            // Each hop is loaded and written back in turn, so pools or vaults repeated along the path stay current
+            let mut pool = Account::<LiquidityPool>::try_from(&hop[0])?;
+            let mut vault = Account::<Vault>::try_from(&hop[1])?;
+            let pool_authority = &hop[2];
+            let in_mint = InterfaceAccount::<token_interface::Mint>::try_from(&hop[3])?;
+            let out_mint = InterfaceAccount::<token_interface::Mint>::try_from(&hop[4])?;
+            let pool_in = InterfaceAccount::<token_interface::TokenAccount>::try_from(&hop[5])?;
+            let pool_out = InterfaceAccount::<token_interface::TokenAccount>::try_from(&hop[6])?;
+            let protocol_fee_account = InterfaceAccount::<token_interface::TokenAccount>::try_from(&hop[7])?;
+            let authority_fee_account = InterfaceAccount::<token_interface::TokenAccount>::try_from(&hop[8])?;
+            let user_out = InterfaceAccount::<token_interface::TokenAccount>::try_from(&hop[9])?;
+            let in_program = Interface::<TokenInterface>::try_from(&hop[10])?;
+            let out_program = Interface::<TokenInterface>::try_from(&hop[11])?;
+            let mut holding = Account::<Holding>::try_from(&hop[12])?;
+            let mut observations = Account::<PriceObservations>::try_from(&hop[13])?;
+            let user_in = match previous_out {
+                Some(info) => InterfaceAccount::<token_interface::TokenAccount>::try_from(info)?,
+                None => ctx.accounts.user_source.clone(),
+            };
+
+            require_keys_eq!(vault.key(), pool.vault, MeteraError::InvalidRoute);
+            require!(
+                pool_authority.is_signer && pool_authority.key() == pool.pool_authority,
+                MeteraError::InvalidRoute
+            );
+            require!(
+                (in_mint.key() == pool.token_a_mint && out_mint.key() == pool.token_b_mint)
+                    || (in_mint.key() == pool.token_b_mint && out_mint.key() == pool.token_a_mint),
+                MeteraError::InvalidPoolMint
+            );
+            require!(
+                pool_in.mint == in_mint.key() && pool_in.owner == pool.pool_authority
+                    && pool_out.mint == out_mint.key() && pool_out.owner == pool.pool_authority,
+                MeteraError::InvalidRoute
+            );
+            require!(
+                user_in.mint == in_mint.key() && user_in.owner == user_key && user_out.mint == out_mint.key() && user_out.owner == user_key,
+                MeteraError::InvalidRoute
+            );
+
+            require!(holding.vault == vault.key() && holding.owner == user_key, MeteraError::InvalidRoute);
+            require_keys_eq!(observations.pool, pool.key(), MeteraError::InvalidRoute);
+            let a_to_b = in_mint.key() == pool.token_a_mint;
+            let (amount_out, lp_fee) = SwapLeg {
+                pool: &mut pool,
+                vault: &mut vault,
+                holding: &mut holding,
+                in_program: &in_program,
+                in_mint: &in_mint,
+                user_in: &user_in,
+                pool_in: &pool_in,
+                out_program: &out_program,
+                out_mint: &out_mint,
+                user_out: &user_out,
+                pool_out: &pool_out,
+                protocol_fee_account: &protocol_fee_account,
+                authority_fee_account: &authority_fee_account,
+                user: ctx.accounts.user.to_account_info(),
+                pool_authority: pool_authority.clone(),
+            }
+            .execute(hop_amount, swap_fee_bps, ctx.remaining_accounts, current_time)?;
+            observations.record(&pool, current_time)?;
+            pool.exit(&crate::ID)?;
+            vault.exit(&crate::ID)?;
+            holding.exit(&crate::ID)?;
+            observations.exit(&crate::ID)?;
+
+            emit!(TokensSwapped {
+                pool: pool.key(),
+                user: user_key,
+                amount_in: hop_amount,
+                amount_out,
+                a_to_b,
+                lp_fee,
+            });
+
+            pools.push(pool.key());
+            hop_amount = amount_out;
+            destination_mint = out_mint.key();
+            previous_out = Some(&hop[9]);
+        }
+
+        require!(hop_amount >= minimum_amount_out, MeteraError::SlippageExceeded);
+
+        emit!(RouteSwapped {
+            user: user_key,
+            pools,
+            source_mint: ctx.accounts.user_source.mint,
+            destination_mint,
+            amount_in,
+            amount_out: hop_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Create a pool's price observation ring buffer, seeded with the current accumulators
+    pub fn initialize_price_observations(ctx: Context<InitializePriceObservations>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let pool = &mut ctx.accounts.liquidity_pool;
+        pool.accumulate_prices(current_time)?;
+
+        let observations = &mut ctx.accounts.price_observations;
+        observations.pool = pool.key();
+        observations.head = 0;
+        observations.observations = Vec::new();
+        observations.bump = ctx.bumps.price_observations;
+        observations.record(pool, current_time)?;
+
+        emit!(PriceObservationsInitialized {
+            pool: pool.key(),
+            price_observations: observations.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Crank: bring the pool's price accumulators up to date and snapshot them
+    pub fn record_price_observation(ctx: Context<RecordPriceObservation>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let pool = &mut ctx.accounts.liquidity_pool;
+        pool.accumulate_prices(current_time)?;
+        ctx.accounts.price_observations.record(pool, current_time)
+    }
+
+    /// Time-weighted average prices over at least the last `interval` seconds, measured from the newest
+    /// observation at or before the start of the interval. Prices are Q64.64: A in units of B, and B in units of A.
+    pub fn get_twap(ctx: Context<GetTwap>, interval: u32) -> Result<Twap> {
+        require!(interval > 0, MeteraError::OracleHistoryTooShort);
+        let current_time = Clock::get()?.unix_timestamp;
+        let (price_a_cumulative, price_b_cumulative) = ctx.accounts.liquidity_pool.cumulative_prices_at(current_time)?;
+
+        let start = ctx
+            .accounts
+            .price_observations
+            .at_or_before(current_time - interval as i64)
+            .ok_or(MeteraError::OracleHistoryTooShort)?;
+        let elapsed = (current_time - start.timestamp) as u128;
+
+        Ok(Twap {
+            price_a: price_a_cumulative.wrapping_sub(start.price_a_cumulative) / elapsed,
+            price_b: price_b_cumulative.wrapping_sub(start.price_b_cumulative) / elapsed,
+            start: start.timestamp,
+            end: current_time,
+        })
+    }
+
+    /// Lend `amount` of one side of the pool's vault tokens for the rest of the transaction. A top-level
+    /// `flash_repay` for the same pool must follow later in the transaction.
+    pub fn flash_borrow<'info>(ctx: Context<'_, '_, '_, 'info, FlashBorrow<'info>>, amount: u64, token_a: bool) -> Result<()> {
+        require!(get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT, MeteraError::InvalidFlashLoan);
+        require!(amount > 0, MeteraError::InvalidFlashLoan);
+        ctx.accounts.investor_record.check_eligible(Clock::get()?.unix_timestamp)?;
+
+        let pool = &mut ctx.accounts.liquidity_pool;
+        require!(pool.is_active, MeteraError::PoolInactive);
+        require!(pool.total_lp_shares > 0, MeteraError::ZeroLiquidityPool);
+        require!(pool.flash_loan_amount == 0, MeteraError::FlashLoanActive);
+        let mint = if token_a { pool.token_a_mint } else { pool.token_b_mint };
+        require_keys_eq!(ctx.accounts.mint.key(), mint, MeteraError::InvalidPoolMint);
+
+        // Find the matching repayment among the instructions after this one
+        let instructions = ctx.accounts.instructions_sysvar.to_account_info();
+        let mut index = instructions_sysvar::load_current_index_checked(&instructions)? as usize + 1;
+        let mut repaid = false;
+        while let Ok(ix) = instructions_sysvar::load_instruction_at_checked(index, &instructions) {
+            if ix.program_id == crate::ID
+                && ix.data.starts_with(&<instruction::FlashRepay as anchor_lang::Discriminator>::DISCRIMINATOR)
+                && ix.accounts.first().map(|meta| meta.pubkey) == Some(pool.key())
+            {
+                repaid = true;
+                break;
+            }
+            index += 1;
+        }
+        require!(repaid, MeteraError::FlashLoanNotRepaid);
+
+        if mint == ctx.accounts.vault.token_mint {
+            ctx.accounts.vault.record_incoming(&mut ctx.accounts.borrower_holding, amount)?;
+        }
+
+        transfer_checked_with_hook(
+            &ctx.accounts.token_program,
+            &ctx.accounts.pool_token_vault,
+            &ctx.accounts.mint,
+            &ctx.accounts.borrower_token_account,
+            ctx.accounts.pool_authority.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            &[],
+        )?;
+
+        pool.flash_loan_amount = amount;
+        pool.flash_loan_token_a = token_a;
+
+        emit!(FlashLoanBorrowed {
+            pool: pool.key(),
+            borrower: ctx.accounts.borrower.key(),
+            mint,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Return the outstanding flash loan plus its fee; the fee accrues to LP positions like the LP swap fee
+    pub fn flash_repay<'info>(ctx: Context<'_, '_, '_, 'info, FlashRepay<'info>>) -> Result<()> {
+        let pool = &mut ctx.accounts.liquidity_pool;
+        let amount = pool.flash_loan_amount;
+        require!(amount > 0, MeteraError::InvalidFlashLoan);
+        let mint = if pool.flash_loan_token_a { pool.token_a_mint } else { pool.token_b_mint };
+        require_keys_eq!(ctx.accounts.mint.key(), mint, MeteraError::InvalidPoolMint);
+
+        let fee = (amount as u128)
+            .checked_mul(FLASH_LOAN_FEE_BPS as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .div_ceil(BPS_DENOMINATOR as u128) as u64;
+        let repayment = amount.checked_add(fee).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        if mint == ctx.accounts.vault.token_mint {
+            ctx.accounts.vault.record_outgoing(&mut ctx.accounts.borrower_holding, repayment)?;
+        }
+
+        transfer_checked_with_hook(
+            &ctx.accounts.token_program,
+            &ctx.accounts.borrower_token_account,
+            &ctx.accounts.mint,
+            &ctx.accounts.pool_token_vault,
+            ctx.accounts.borrower.to_account_info(),
+            ctx.remaining_accounts,
+            repayment,
+            &[],
+        )?;
+
+        let fee_growth = ((fee as u128) << FEE_GROWTH_SHIFT)
+            .checked_div(pool.total_lp_shares as u128)
+            .ok_or(MeteraError::ZeroLiquidityPool)?;
+        if pool.flash_loan_token_a {
+            pool.fee_growth_a = pool.fee_growth_a.checked_add(fee_growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        } else {
+            pool.fee_growth_b = pool.fee_growth_b.checked_add(fee_growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        }
+        pool.flash_loan_amount = 0;
+
+        emit!(FlashLoanRepaid {
+            pool: pool.key(),
+            borrower: ctx.accounts.borrower.key(),
+            mint,
+            amount,
+            fee,
+        });
+
+        Ok(())
+    }
+
+    /// Redeem part of a pool's vault-token reserve at par against unused monthly capacity.
+    /// `payment_amount` of the proceeds buys the tokens along the curve as if swapped in; the rest is spread credited to LPs
+    pub fn pool_assisted_redeem(
+        ctx: Context<PoolAssistedRedeem>,
+        payment_amount: u64,
+        min_spread: u64,
+    ) -> Result<()> {
+        require!(payment_amount > 0, MeteraError::InvalidSwapAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+
+        let pool = &mut ctx.accounts.liquidity_pool;
+        require!(pool.is_active, MeteraError::PoolInactive);
+        let token_is_a = pool.token_a_mint == vault.token_mint && pool.token_b_mint == vault.payment_mint;
+        require!(
+            token_is_a || (pool.token_b_mint == vault.token_mint && pool.token_a_mint == vault.payment_mint),
+            MeteraError::InvalidPoolMint
+        );
+        pool.accumulate_prices(now)?;
+
+        // Quote the vault tokens the payment buys from the pool's own reserve
+        let (token_reserve, payment_reserve) = if token_is_a {
+            (pool.token_a_reserve, pool.token_b_reserve)
+        } else {
+            (pool.token_b_reserve, pool.token_a_reserve)
+        };
+        let token_amount = pool.swap_out(payment_amount, payment_reserve, token_reserve, now)?;
+        require!(token_amount > 0, MeteraError::InsufficientLiquidity);
+
+        let monthly_payment_record = &mut ctx.accounts.monthly_payment_record;
+        require!(monthly_payment_record.available_for_redemption >= token_amount, MeteraError::InsufficientRedemptionCapacity);
+
+        // Redeem at par, net of the redemption fee
+        let fee = FeeCharge::new(
+            FeeKind::Redemption,
+            token_amount,
+            ctx.accounts.protocol_config.protocol_fees.redemption_fee_bps,
+            vault.authority_fees.redemption_fee_bps,
+        )?;
+        let redemption_value = fee.net()?;
+        let spread = redemption_value.checked_sub(payment_amount).ok_or(MeteraError::NoRedemptionSpread)?;
+        require!(spread > 0 && spread >= min_spread, MeteraError::NoRedemptionSpread);
+
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.pool_token_vault.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+            ),
+            token_amount,
+        )?;
+
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.vault_treasury.to_account_info(),
+            ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault.key(),
+            &fee,
+            &[],
+        )?;
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.vault_treasury.to_account_info(),
+                    to: ctx.accounts.pool_payment_vault.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            redemption_value,
+        )?;
+
+        // The swapped-in payment joins the reserves; the spread stays outside them and accrues to LP positions
+        let fee_growth = ((spread as u128) << FEE_GROWTH_SHIFT)
+            .checked_div(pool.total_lp_shares as u128)
+            .ok_or(MeteraError::ZeroLiquidityPool)?;
+        if token_is_a {
+            pool.token_a_reserve = token_reserve.checked_sub(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.token_b_reserve = payment_reserve.checked_add(payment_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.fee_growth_b = pool.fee_growth_b.checked_add(fee_growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        } else {
+            pool.token_b_reserve = token_reserve.checked_sub(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.token_a_reserve = payment_reserve.checked_add(payment_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.fee_growth_a = pool.fee_growth_a.checked_add(fee_growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        }
+
+        ctx.accounts.price_observations.record(pool, now)?;
+        monthly_payment_record.available_for_redemption = monthly_payment_record.available_for_redemption
+            .checked_sub(token_amount)
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        vault.total_redeemed = vault.total_redeemed
+            .checked_add(token_amount)
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(PoolRedemption {
+            pool: pool.key(),
+            vault: vault.key(),
+            month: monthly_payment_record.month,
+            payment_amount,
+            token_amount,
+            redemption_value,
+            spread,
+        });
+
+        Ok(())
+    }
+
+    /// Collect the swap fees a position has earned without withdrawing its liquidity
+    pub fn claim_lp_fees<'info>(ctx: Context<'_, '_, '_, 'info, ClaimLpFees<'info>>) -> Result<()> {
+        let pool = &ctx.accounts.liquidity_pool;
+        let lp_position = &mut ctx.accounts.lp_position;
+        lp_position.accrue_fees(pool.fee_growth_a, pool.fee_growth_b)?;
+
+        let token_a_amount = lp_position.fees_owed_a;
+        let token_b_amount = lp_position.fees_owed_b;
+        require!(token_a_amount > 0 || token_b_amount > 0, MeteraError::NoFeesToClaim);
+
+        let vault = &mut ctx.accounts.vault;
+        if pool.token_a_mint == vault.token_mint {
+            vault.record_incoming(&mut ctx.accounts.lp_holding, token_a_amount)?;
+        }
+        if pool.token_b_mint == vault.token_mint {
+            vault.record_incoming(&mut ctx.accounts.lp_holding, token_b_amount)?;
+        }
+
+        if token_a_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.pool_token_a_vault,
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.lp_token_a_account,
+                ctx.accounts.pool_authority.to_account_info(),
+                ctx.remaining_accounts,
+                token_a_amount,
+                &[],
+            )?;
+        }
+
+        if token_b_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.pool_token_b_vault,
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.lp_token_b_account,
+                ctx.accounts.pool_authority.to_account_info(),
+                ctx.remaining_accounts,
+                token_b_amount,
+                &[],
+            )?;
+        }
+
+        lp_position.fees_owed_a = 0;
+        lp_position.fees_owed_b = 0;
+
+        emit!(LpFeesClaimed {
+            pool: pool.key(),
+            lp: ctx.accounts.lp.key(),
+            lp_position: lp_position.key(),
+            token_a_amount,
+            token_b_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Re-quote a committed position's forward discount; allowed once per monthly period of its window
+    pub fn requote_forward_discount(ctx: Context<RequoteForwardDiscount>, forward_discount_percentage: u8) -> Result<()> {
+        require!(forward_discount_percentage <= 100, MeteraError::InvalidDiscount);
+
+        let pool = &ctx.accounts.liquidity_pool;
+        let lp_position = &mut ctx.accounts.lp_position;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time < pool.window_end(lp_position.window_number)?, MeteraError::WindowClosed);
+
+        let period = pool.quote_period(lp_position.window_number, current_time)?;
+        require!(period > lp_position.quote_period, MeteraError::RequoteNotDue);
+
+        lp_position.forward_discount_percentage = forward_discount_percentage;
+        lp_position.quote_period = period;
+
+        emit!(ForwardDiscountRequoted {
+            pool: pool.key(),
+            lp: ctx.accounts.lp.key(),
+            lp_position: lp_position.key(),
+            window_number: lp_position.window_number,
+            quote_period: period,
+            forward_discount_percentage,
+        });
+
+        Ok(())
+    }
+
+    /// Carry a position whose window has closed into the current window instead of exiting
+    pub fn roll_lp_position(ctx: Context<RollLpPosition>, window_number: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let pool = &mut ctx.accounts.liquidity_pool;
+        require!(window_number == pool.sync_window(current_time)?, MeteraError::WindowMismatch);
+
+        let expiring = &mut ctx.accounts.lp_position;
+        require!(current_time >= pool.window_end(expiring.window_number)?, MeteraError::LiquidityLocked);
+        expiring.accrue_fees(pool.fee_growth_a, pool.fee_growth_b)?;
+
+        let next = &mut ctx.accounts.next_lp_position;
+        next.open_if_new(pool, pool.key(), ctx.accounts.lp.key(), window_number, current_time, ctx.bumps.next_lp_position)?;
+        next.accrue_fees(pool.fee_growth_a, pool.fee_growth_b)?;
+        next.add_deposit(expiring.token_a_amount, expiring.token_b_amount, expiring.lp_shares, expiring.forward_discount_percentage)?;
+        next.fees_owed_a = next.fees_owed_a.checked_add(expiring.fees_owed_a).ok_or(MeteraError::ArithmeticOverflow)?;
+        next.fees_owed_b = next.fees_owed_b.checked_add(expiring.fees_owed_b).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        // The shares follow the position into the next window's escrow
+        let pool_key = pool.key();
+        let lp_key = ctx.accounts.lp.key();
+        let window = expiring.window_number.to_le_bytes();
+        let seeds: &[&[u8]] = &[LP_POSITION_SEED, pool_key.as_ref(), lp_key.as_ref(), &window, &[expiring.bump]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.lp_share_escrow.to_account_info(),
+                    to: ctx.accounts.next_lp_share_escrow.to_account_info(),
+                    authority: expiring.to_account_info(),
+                },
+                &[seeds],
+            ),
+            ctx.accounts.lp_share_escrow.amount,
+        )?;
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token::CloseAccount {
+                account: ctx.accounts.lp_share_escrow.to_account_info(),
+                destination: ctx.accounts.lp.to_account_info(),
+                authority: expiring.to_account_info(),
+            },
+            &[seeds],
+        ))?;
+
+        emit!(LpPositionRolled {
+            pool: pool.key(),
+            lp: ctx.accounts.lp.key(),
+            from_window: expiring.window_number,
+            to_window: window_number,
+            lp_shares: expiring.lp_shares,
+        });
+
+        Ok(())
+    }
+
+    /// Move a position's escrowed shares into the LP's own account once its window has ended
+    pub fn release_lp_shares(ctx: Context<ReleaseLpShares>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let pool = &ctx.accounts.liquidity_pool;
+        let lp_position = &mut ctx.accounts.lp_position;
+        require!(current_time >= pool.window_end(lp_position.window_number)?, MeteraError::LiquidityLocked);
+
+        let lp_shares = ctx.accounts.lp_share_escrow.amount;
+        require!(lp_shares > 0, MeteraError::InsufficientLPShares);
+
+        // Fees earned while the shares were committed stay claimable on the position
+        lp_position.accrue_fees(pool.fee_growth_a, pool.fee_growth_b)?;
+        lp_position.remove_shares(lp_shares)?;
+
+        let pool_key = pool.key();
+        let lp_key = ctx.accounts.lp.key();
+        let window = lp_position.window_number.to_le_bytes();
+        let seeds: &[&[u8]] = &[LP_POSITION_SEED, pool_key.as_ref(), lp_key.as_ref(), &window, &[lp_position.bump]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.lp_share_escrow.to_account_info(),
+                    to: ctx.accounts.lp_share_account.to_account_info(),
+                    authority: lp_position.to_account_info(),
+                },
+                &[seeds],
+            ),
+            lp_shares,
+        )?;
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token::CloseAccount {
+                account: ctx.accounts.lp_share_escrow.to_account_info(),
+                destination: ctx.accounts.lp.to_account_info(),
+                authority: lp_position.to_account_info(),
+            },
+            &[seeds],
+        ))?;
+
+        emit!(LpSharesReleased {
+            pool: pool_key,
+            lp: lp_key,
+            lp_position: lp_position.key(),
+            window_number: lp_position.window_number,
+            lp_shares,
+        });
+
+        Ok(())
+    }
+
+    /// Ramp a StableSwap pool's amplification coefficient linearly to `target_amp` by `ramp_end`
+    pub fn ramp_amplification(ctx: Context<RampAmplification>, target_amp: u64, ramp_end: i64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let pool = &mut ctx.accounts.liquidity_pool;
+        require!(pool.curve == CurveType::StableSwap, MeteraError::InvalidCurve);
+        require!((MIN_AMP..=MAX_AMP).contains(&target_amp), MeteraError::InvalidAmplification);
+        require!(
+            ramp_end >= current_time.checked_add(MIN_AMP_RAMP_DURATION).ok_or(MeteraError::ArithmeticOverflow)?,
+            MeteraError::InvalidAmplification
+        );
+
+        let current_amp = pool.amp_at(current_time)?;
+        require!(
+            target_amp <= current_amp.saturating_mul(MAX_AMP_CHANGE) && current_amp <= target_amp.saturating_mul(MAX_AMP_CHANGE),
+            MeteraError::InvalidAmplification
+        );
+
+        pool.amp_initial = current_amp;
+        pool.amp_target = target_amp;
+        pool.amp_ramp_start = current_time;
+        pool.amp_ramp_end = ramp_end;
+
+        emit!(AmplificationRamped {
+            pool: pool.key(),
+            amp_initial: current_amp,
+            amp_target: target_amp,
+            ramp_start: current_time,
+            ramp_end,
+        });
+
+        Ok(())
+    }
+
+    /// Attach a discount book to a vault-token/payment-token pool for concentrated buy-side liquidity
+    pub fn create_discount_book(ctx: Context<CreateDiscountBook>) -> Result<()> {
+        let pool = &ctx.accounts.liquidity_pool;
+        let vault = &ctx.accounts.vault;
+        require!(
+            pool.token_a_mint == vault.token_mint && pool.token_b_mint == vault.payment_mint,
+            MeteraError::InvalidPoolMint
+        );
+
+        let book = &mut ctx.accounts.discount_book;
+        book.pool = pool.key();
+        book.current_tick = 0;
+        book.current_fill = 0;
+        book.tick_liquidity = [0; DISCOUNT_TICKS];
+        book.tick_crossings = 0;
+        book.vault_tokens_held = 0;
+        book.payment_held = 0;
+        book.bump = ctx.bumps.discount_book;
+
+        emit!(DiscountBookCreated {
+            pool: pool.key(),
+            discount_book: book.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Quote `liquidity` payment tokens at every discount tick from `lower_tick` to `upper_tick`.
+    /// Ticks the market has already passed are funded in vault tokens, as if they had filled.
+    pub fn provide_band_liquidity<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProvideBandLiquidity<'info>>,
+        lower_tick: u8,
+        upper_tick: u8,
+        liquidity: u64,
+    ) -> Result<()> {
+        require!(
+            liquidity > 0 && lower_tick <= upper_tick && (upper_tick as usize) < DISCOUNT_TICKS,
+            MeteraError::InvalidDiscountBand
+        );
+        require!(ctx.accounts.liquidity_pool.is_active, MeteraError::PoolInactive);
+        ctx.accounts.investor_record.check_eligible(Clock::get()?.unix_timestamp)?;
+
+        let book = &mut ctx.accounts.discount_book;
+        let (vault_token_amount, payment_amount) = book.band_amounts(lower_tick, upper_tick, liquidity, true)?;
+
+        ctx.accounts.vault.record_outgoing(&mut ctx.accounts.lp_holding, vault_token_amount)?;
+
+        if vault_token_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.lp_token_a_account,
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.pool_token_a_vault,
+                ctx.accounts.lp.to_account_info(),
+                ctx.remaining_accounts,
+                vault_token_amount,
+                &[],
+            )?;
+        }
+        if payment_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.lp_token_b_account,
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.pool_token_b_vault,
+                ctx.accounts.lp.to_account_info(),
+                ctx.remaining_accounts,
+                payment_amount,
+                &[],
+            )?;
+        }
+
+        book.update_band(lower_tick, upper_tick, liquidity, true)?;
+        book.vault_tokens_held = book.vault_tokens_held.checked_add(vault_token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        book.payment_held = book.payment_held.checked_add(payment_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let position = &mut ctx.accounts.band_position;
+        if position.pool == Pubkey::default() {
+            position.pool = ctx.accounts.liquidity_pool.key();
+            position.lp = ctx.accounts.lp.key();
+            position.lower_tick = lower_tick;
+            position.upper_tick = upper_tick;
+            position.bump = ctx.bumps.band_position;
+        }
+        position.liquidity = position.liquidity.checked_add(liquidity).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(BandLiquidityProvided {
+            pool: ctx.accounts.liquidity_pool.key(),
+            lp: ctx.accounts.lp.key(),
+            lower_tick,
+            upper_tick,
+            liquidity,
+            vault_token_amount,
+            payment_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw `liquidity` per tick from a band, in whatever mix of vault and payment tokens it now holds
+    pub fn withdraw_band_liquidity<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawBandLiquidity<'info>>,
+        liquidity: u64,
+        min_vault_tokens: u64,
+        min_payment: u64,
+    ) -> Result<()> {
+        let position = &mut ctx.accounts.band_position;
+        require!(liquidity > 0 && liquidity <= position.liquidity, MeteraError::InsufficientLPShares);
+
+        let book = &mut ctx.accounts.discount_book;
+        let (vault_token_amount, payment_amount) = book.band_amounts(position.lower_tick, position.upper_tick, liquidity, false)?;
+        // Rounding dust can leave the book a unit short of what the last band is owed
+        let vault_token_amount = vault_token_amount.min(book.vault_tokens_held);
+        let payment_amount = payment_amount.min(book.payment_held);
+        require!(
+            vault_token_amount >= min_vault_tokens && payment_amount >= min_payment,
+            MeteraError::SlippageExceeded
+        );
+
+        ctx.accounts.vault.record_incoming(&mut ctx.accounts.lp_holding, vault_token_amount)?;
+
+        if vault_token_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.pool_token_a_vault,
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.lp_token_a_account,
+                ctx.accounts.pool_authority.to_account_info(),
+                ctx.remaining_accounts,
+                vault_token_amount,
+                &[],
+            )?;
+        }
+        if payment_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.pool_token_b_vault,
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.lp_token_b_account,
+                ctx.accounts.pool_authority.to_account_info(),
+                ctx.remaining_accounts,
+                payment_amount,
+                &[],
+            )?;
+        }
+
+        book.update_band(position.lower_tick, position.upper_tick, liquidity, false)?;
+        book.vault_tokens_held = book.vault_tokens_held.checked_sub(vault_token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        book.payment_held = book.payment_held.checked_sub(payment_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        position.liquidity = position.liquidity.checked_sub(liquidity).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(BandLiquidityWithdrawn {
+            pool: ctx.accounts.liquidity_pool.key(),
+            lp: ctx.accounts.lp.key(),
+            lower_tick: position.lower_tick,
+            upper_tick: position.upper_tick,
+            liquidity,
+            vault_token_amount,
+            payment_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Trade against the discount book: `a_to_b` sells vault tokens into the bands, crossing ticks toward deeper discounts,
+    /// and `!a_to_b` buys back filled vault tokens from the shallowest filled tick down
+    pub fn swap_discount_bands<'info>(
+        ctx: Context<'_, '_, '_, 'info, SwapDiscountBands<'info>>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        a_to_b: bool,
+    ) -> Result<()> {
+        require!(amount_in > 0, MeteraError::InvalidSwapAmount);
+        require!(ctx.accounts.liquidity_pool.is_active, MeteraError::PoolInactive);
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.investor_record.check_eligible(current_time)?;
+
+        let (in_program, in_mint, user_in, pool_in, out_program, out_mint, user_out, pool_out) = if a_to_b {
+            (
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.user_token_a_account,
+                &ctx.accounts.pool_token_a_vault,
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.user_token_b_account,
+                &ctx.accounts.pool_token_b_vault,
+            )
+        } else {
+            (
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.user_token_b_account,
+                &ctx.accounts.pool_token_b_vault,
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.user_token_a_account,
+                &ctx.accounts.pool_token_a_vault,
+            )
+        };
+
+        // Band LPs earn the discount spread, so only the protocol and authority swap fees apply
+        let vault = &mut ctx.accounts.vault;
+        let fee = FeeCharge::new(
+            FeeKind::Swap,
+            amount_in,
+            ctx.accounts.protocol_config.protocol_fees.swap_fee_bps,
+            vault.authority_fees.swap_fee_bps,
+        )?;
+        let amount_in_after_fee = fee.net()?;
+
+        let book = &mut ctx.accounts.discount_book;
+        let start_tick = book.current_tick;
+        let (amount_out, ticks_crossed) = if a_to_b {
+            book.sell_vault_tokens(amount_in_after_fee)?
+        } else {
+            book.buy_vault_tokens(amount_in_after_fee)?
+        };
+        require!(amount_out > 0, MeteraError::InsufficientLiquidity);
+        require!(amount_out >= minimum_amount_out, MeteraError::SlippageExceeded);
+
+        if a_to_b {
+            vault.record_outgoing(&mut ctx.accounts.user_holding, amount_in)?;
+            book.vault_tokens_held = book.vault_tokens_held.checked_add(amount_in_after_fee).ok_or(MeteraError::ArithmeticOverflow)?;
+            book.payment_held = book.payment_held.checked_sub(amount_out).ok_or(MeteraError::InsufficientLiquidity)?;
+        } else {
+            vault.record_incoming(&mut ctx.accounts.user_holding, amount_out)?;
+            book.payment_held = book.payment_held.checked_add(amount_in_after_fee).ok_or(MeteraError::ArithmeticOverflow)?;
+            book.vault_tokens_held = book.vault_tokens_held.checked_sub(amount_out).ok_or(MeteraError::InsufficientLiquidity)?;
+        }
+        book.tick_crossings = book.tick_crossings.checked_add(ticks_crossed as u64).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let vault_key = vault.key();
+        require_keys_eq!(
+            ctx.accounts.protocol_fee_account.key(),
+            fee_account_address(PROTOCOL_FEE_SEED, &vault_key, &in_mint.key()),
+            MeteraError::InvalidFeeAccount
+        );
+        require_keys_eq!(
+            ctx.accounts.authority_fee_account.key(),
+            fee_account_address(AUTHORITY_FEE_SEED, &vault_key, &in_mint.key()),
+            MeteraError::InvalidFeeAccount
+        );
+
+        for (fee_account, fee_amount) in [
+            (&ctx.accounts.protocol_fee_account, fee.protocol_fee),
+            (&ctx.accounts.authority_fee_account, fee.authority_fee),
+        ] {
+            if fee_amount > 0 {
+                transfer_checked_with_hook(
+                    in_program,
+                    user_in,
+                    in_mint,
+                    fee_account,
+                    ctx.accounts.user.to_account_info(),
+                    ctx.remaining_accounts,
+                    fee_amount,
+                    &[],
+                )?;
+            }
+        }
+
+        transfer_checked_with_hook(
+            in_program,
+            user_in,
+            in_mint,
+            pool_in,
+            ctx.accounts.user.to_account_info(),
+            ctx.remaining_accounts,
+            amount_in_after_fee,
+            &[],
+        )?;
+
+        transfer_checked_with_hook(
+            out_program,
+            pool_out,
+            out_mint,
+            user_out,
+            ctx.accounts.pool_authority.to_account_info(),
+            ctx.remaining_accounts,
+            amount_out,
+            &[],
+        )?;
+        ctx.accounts.price_observations.record(&ctx.accounts.liquidity_pool, current_time)?;
+
+        emit!(FeeCharged {
+            vault: vault_key,
+            kind: FeeKind::Swap,
+            mint: in_mint.key(),
+            amount: amount_in,
+            protocol_fee: fee.protocol_fee,
+            authority_fee: fee.authority_fee,
+        });
+
+        emit!(DiscountBandsSwapped {
+            pool: ctx.accounts.liquidity_pool.key(),
+            user: ctx.accounts.user.key(),
+            amount_in,
+            amount_out,
+            a_to_b,
+            start_tick,
+            end_tick: book.current_tick,
+            ticks_crossed,
+        });
+
+        Ok(())
+    }
+
+    // ============ COMPLIANCE & INVESTOR REGISTRY ============
+
+    /// Create the protocol configuration; only the program's upgrade authority may sign, and becomes its admin
+    pub fn initialize_protocol_config(ctx: Context<InitializeProtocolConfig>, attester: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.protocol_config;
+        config.admin = ctx.accounts.admin.key();
+        config.attester = attester;
+        config.protocol_fees = FeeSchedule::default();
+        config.bump = ctx.bumps.protocol_config;
+
+        emit!(AttesterUpdated {
+            admin: config.admin,
+            attester,
+        });
+
+        Ok(())
+    }
+
+    /// Rotate the key allowed to write investor records
+    pub fn set_attester(ctx: Context<SetAttester>, attester: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.protocol_config;
+        config.attester = attester;
+
+        emit!(AttesterUpdated {
+            admin: config.admin,
+            attester,
+        });
+
+        Ok(())
+    }
+
+    /// Create or update a wallet's KYC record
+    pub fn attest_investor(
+        ctx: Context<AttestInvestor>,
+        kyc_status: KycStatus,
+        jurisdiction: [u8; 2],
+        is_accredited: bool,
+        expires_at: i64,
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(
+            kyc_status != KycStatus::Verified || expires_at > current_time,
+            MeteraError::InvalidAttestation
+        );
+
+        let record = &mut ctx.accounts.investor_record;
+        record.wallet = ctx.accounts.wallet.key();
+        record.kyc_status = kyc_status;
+        record.jurisdiction = jurisdiction;
+        record.is_accredited = is_accredited;
+        record.expires_at = expires_at;
+        record.attested_by = ctx.accounts.attester.key();
+        record.updated_at = current_time;
+        record.bump = ctx.bumps.investor_record;
+
+        emit!(InvestorAttested {
+            wallet: record.wallet,
+            attester: record.attested_by,
+            kyc_status,
+            jurisdiction,
+            is_accredited,
+            expires_at,
+        });
+
+        Ok(())
+    }
+
+    /// Assign the key allowed to freeze, thaw and force-transfer the vault's tokens
+    pub fn set_compliance_officer(ctx: Context<SetComplianceOfficer>, compliance_officer: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.compliance_officer = compliance_officer;
+
+        emit!(ComplianceOfficerUpdated {
+            vault: vault.key(),
+            compliance_officer,
+        });
+
+        Ok(())
+    }
+
+    /// Set the per-wallet holding cap and maximum holder count; zero disables a limit
+    pub fn set_holding_limits(ctx: Context<SetHoldingLimits>, max_holding_per_wallet: u64, max_holder_count: u32) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.max_holding_per_wallet = max_holding_per_wallet;
+        vault.max_holder_count = max_holder_count;
+
+        emit!(HoldingLimitsUpdated {
+            vault: vault.key(),
+            max_holding_per_wallet,
+            max_holder_count,
+        });
+
+        Ok(())
+    }
+
+    /// Open the account that tracks a wallet's vault tokens; a wallet needs one before it can receive them
+    pub fn open_holding(ctx: Context<OpenHolding>) -> Result<()> {
+        let holding = &mut ctx.accounts.holding;
+        holding.vault = ctx.accounts.vault.key();
+        holding.owner = ctx.accounts.owner.key();
+        holding.amount = 0;
+        holding.bump = ctx.bumps.holding;
+
+        emit!(HoldingOpened {
+            vault: holding.vault,
+            owner: holding.owner,
+        });
+
+        Ok(())
+    }
+
+    /// Apply the holding limits to a secondary transfer; called by the transfer hook after Token-2022 moves the tokens.
+    /// The hook leaves out the destination holding when the tokens move into one of the vault's escrows.
+    pub fn record_secondary_transfer(ctx: Context<RecordSecondaryTransfer>, amount: u64) -> Result<()> {
+        // Moving tokens between a wallet's own accounts leaves its holding unchanged
+        if ctx.accounts.source_token_account.owner == ctx.accounts.destination_token_account.owner {
+            return Ok(());
+        }
+
+        let vault = &mut ctx.accounts.vault;
+        vault.record_outgoing(&mut ctx.accounts.source_holding, amount)?;
+        if let Some(destination_holding) = &mut ctx.accounts.destination_holding {
+            vault.record_incoming(destination_holding, amount)?;
+        }
+
+        Ok(())
+    }
+
+    /// Freeze a holder's vault token account
+    pub fn freeze_holder_account(ctx: Context<ComplianceFreeze>, reason_code: u16) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+
+        token_interface::freeze_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::FreezeAccount {
+                account: ctx.accounts.holder_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                authority: ctx.accounts.vault_signer.to_account_info(),
+            },
+            &[seeds],
+        ))?;
+
+        emit!(HolderAccountFrozen {
+            vault: vault_key,
+            token_account: ctx.accounts.holder_token_account.key(),
+            compliance_officer: ctx.accounts.compliance_officer.key(),
+            reason_code,
+        });
+
+        Ok(())
+    }
+
+    /// Thaw a previously frozen holder account
+    pub fn thaw_holder_account(ctx: Context<ComplianceFreeze>, reason_code: u16) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+
+        token_interface::thaw_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::ThawAccount {
+                account: ctx.accounts.holder_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                authority: ctx.accounts.vault_signer.to_account_info(),
+            },
+            &[seeds],
+        ))?;
+
+        emit!(HolderAccountThawed {
+            vault: vault_key,
+            token_account: ctx.accounts.holder_token_account.key(),
+            compliance_officer: ctx.accounts.compliance_officer.key(),
+            reason_code,
+        });
+
+        Ok(())
+    }
+
+    /// Move a holder's tokens under the mint's permanent delegate (court orders, clawbacks)
+    pub fn force_transfer<'info>(
+        ctx: Context<'_, '_, '_, 'info, ForceTransfer<'info>>,
+        amount: u64,
+        reason_code: u16,
+    ) -> Result<()> {
+        require!(amount > 0, MeteraError::InvalidForceTransfer);
+        require_keys_neq!(
+            ctx.accounts.source_token_account.key(),
+            ctx.accounts.destination_token_account.key(),
+            MeteraError::InvalidForceTransfer
+        );
+
+        // Court orders and clawbacks are not held to the holding limits, but the holder count stays accurate
+        if ctx.accounts.source_token_account.owner != ctx.accounts.destination_token_account.owner {
+            let vault = &mut ctx.accounts.vault;
+            vault.record_outgoing(&mut ctx.accounts.source_holding, amount)?;
+            let destination_holding = &mut ctx.accounts.destination_holding;
+            if destination_holding.amount == 0 {
+                vault.holder_count = vault.holder_count.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+            }
+            destination_holding.amount = destination_holding.amount.checked_add(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        }
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+
+        // A frozen source is thawed for the move and frozen again afterwards
+        let source_frozen = ctx.accounts.source_token_account.is_frozen();
+        if source_frozen {
+            token_interface::thaw_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::ThawAccount {
+                    account: ctx.accounts.source_token_account.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.vault_signer.to_account_info(),
+                },
+                &[seeds],
+            ))?;
+        }
+
+        transfer_checked_with_hook(
+            &ctx.accounts.token_program,
+            &ctx.accounts.source_token_account,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.destination_token_account,
+            ctx.accounts.vault_signer.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            &[seeds],
+        )?;
+
+        if source_frozen {
+            token_interface::freeze_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::FreezeAccount {
+                    account: ctx.accounts.source_token_account.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.vault_signer.to_account_info(),
+                },
+                &[seeds],
+            ))?;
+        }
+
+        emit!(TokensForceTransferred {
+            vault: vault_key,
+            source: ctx.accounts.source_token_account.key(),
+            destination: ctx.accounts.destination_token_account.key(),
+            amount,
+            compliance_officer: ctx.accounts.compliance_officer.key(),
+            reason_code,
+        });
+
+        Ok(())
+    }
+
+    // ============ FEES ============
+
+    /// Set the protocol's fee schedule, charged on every vault
+    pub fn set_protocol_fees(ctx: Context<SetProtocolFees>, fees: FeeSchedule) -> Result<()> {
+        fees.validate()?;
+        ctx.accounts.protocol_config.protocol_fees = fees;
+
+        emit!(FeeScheduleUpdated {
+            vault: None,
+            fees,
+        });
+
+        Ok(())
+    }
+
+    /// Set the vault authority's fee schedule, charged on top of the protocol's
+    pub fn set_vault_fees(ctx: Context<SetVaultFees>, fees: FeeSchedule) -> Result<()> {
+        fees.validate()?;
+        let vault = &mut ctx.accounts.vault;
+        vault.authority_fees = fees;
+
+        emit!(FeeScheduleUpdated {
+            vault: Some(vault.key()),
+            fees,
+        });
+
+        Ok(())
+    }
+
+    /// Create a vault's protocol and vault-authority fee accounts for one mint
+    pub fn create_fee_accounts(ctx: Context<CreateFeeAccounts>) -> Result<()> {
+        emit!(FeeAccountsCreated {
+            vault: ctx.accounts.vault.key(),
+            mint: ctx.accounts.mint.key(),
+            protocol_fee_account: ctx.accounts.protocol_fee_account.key(),
+            authority_fee_account: ctx.accounts.authority_fee_account.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Sweep a vault's fee accounts for one mint to the protocol admin and the vault authority
+    pub fn collect_fees<'info>(ctx: Context<'_, '_, '_, 'info, CollectFees<'info>>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+
+        let protocol_amount = ctx.accounts.protocol_fee_account.amount;
+        let authority_amount = ctx.accounts.authority_fee_account.amount;
+
+        // Fee recipients are the protocol and the issuer rather than investors, so holdings are not tracked
+        if protocol_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_program,
+                &ctx.accounts.protocol_fee_account,
+                &ctx.accounts.mint,
+                &ctx.accounts.protocol_fee_destination,
+                ctx.accounts.vault_signer.to_account_info(),
+                ctx.remaining_accounts,
+                protocol_amount,
+                &[seeds],
+            )?;
+        }
+
+        if authority_amount > 0 {
+            transfer_checked_with_hook(
+                &ctx.accounts.token_program,
+                &ctx.accounts.authority_fee_account,
+                &ctx.accounts.mint,
+                &ctx.accounts.authority_fee_destination,
+                ctx.accounts.vault_signer.to_account_info(),
+                ctx.remaining_accounts,
+                authority_amount,
+                &[seeds],
+            )?;
+        }
+
+        emit!(FeesCollected {
+            vault: vault_key,
+            mint: ctx.accounts.mint.key(),
+            protocol_amount,
+            authority_amount,
+        });
+
+        Ok(())
+    }
+
+    // ============ ORDER BOOK ============
+
+    /// Open a limit order book for the vault token, priced as a discount to par in basis points
+    pub fn create_order_book(ctx: Context<CreateOrderBook>) -> Result<()> {
+        let book = &mut ctx.accounts.order_book;
+        book.vault = ctx.accounts.vault.key();
+        book.next_order_id = 0;
+        book.bids = Vec::new();
+        book.asks = Vec::new();
+        book.fills = Vec::new();
+        book.bump = ctx.bumps.order_book;
+
+        emit!(OrderBookCreated {
+            vault: book.vault,
+            order_book: book.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Rest a limit order on the book; bids escrow payment tokens at their price, asks escrow vault tokens
+    pub fn place_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
+        side: OrderSide,
+        discount_bps: u16,
+        quantity: u64,
+    ) -> Result<()> {
+        require!(quantity > 0 && (discount_bps as u64) < BPS_DENOMINATOR, MeteraError::InvalidOrder);
+        ctx.accounts.investor_record.check_eligible(Clock::get()?.unix_timestamp)?;
+
+        let escrow = match side {
+            OrderSide::Bid => {
+                let price = order_price(quantity, discount_bps, true)?;
+                require!(price > 0, MeteraError::InvalidOrder);
+                token::transfer(
+                    CpiContext::new(
+                        ctx.accounts.token_program.to_account_info(),
+                        token::Transfer {
+                            from: ctx.accounts.trader_payment_account.to_account_info(),
+                            to: ctx.accounts.quote_escrow.to_account_info(),
+                            authority: ctx.accounts.trader.to_account_info(),
+                        },
+                    ),
+                    price,
+                )?;
+                price
+            }
+            OrderSide::Ask => {
+                ctx.accounts.vault.record_outgoing(&mut ctx.accounts.trader_holding, quantity)?;
+                transfer_checked_with_hook(
+                    &ctx.accounts.vault_token_program,
+                    &ctx.accounts.trader_token_account,
+                    &ctx.accounts.token_mint,
+                    &ctx.accounts.base_escrow,
+                    ctx.accounts.trader.to_account_info(),
+                    ctx.remaining_accounts,
+                    quantity,
+                    &[],
+                )?;
+                quantity
+            }
+        };
+
+        let open_orders = &mut ctx.accounts.open_orders;
+        if open_orders.owner == Pubkey::default() {
+            open_orders.owner = ctx.accounts.trader.key();
+            open_orders.order_book = ctx.accounts.order_book.key();
+            open_orders.bump = ctx.bumps.open_orders;
+        }
+
+        let book = &mut ctx.accounts.order_book;
+        let order_id = book.next_order_id;
+        book.next_order_id = order_id.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+        let evicted = book.insert(
+            side,
+            Order {
+                owner: ctx.accounts.trader.key(),
+                order_id,
+                discount_bps,
+                quantity,
+                escrow,
+            },
+        )?;
+
+        // A displaced order's escrow goes back to its owner's open-orders balance
+        if let Some(evicted) = evicted {
+            let evicted_open_orders = if evicted.owner == ctx.accounts.trader.key() {
+                &mut ctx.accounts.open_orders
+            } else {
+                let account = ctx.accounts.evicted_open_orders.as_mut().ok_or(MeteraError::InvalidOpenOrders)?;
+                require_keys_eq!(account.owner, evicted.owner, MeteraError::InvalidOpenOrders);
+                account
+            };
+            evicted_open_orders.release_order(side, &evicted)?;
+
+            emit!(OrderEvicted {
+                order_book: book.key(),
+                owner: evicted.owner,
+                order_id: evicted.order_id,
+                side,
+                quantity: evicted.quantity,
+            });
+        }
+
+        emit!(OrderPlaced {
+            order_book: book.key(),
+            owner: ctx.accounts.trader.key(),
+            order_id,
+            side,
+            discount_bps,
+            quantity,
+        });
+
+        Ok(())
+    }
+
+    /// Pull a resting order; its unfilled escrow is credited to the owner's open-orders balance
+    pub fn cancel_order(ctx: Context<CancelOrder>, order_id: u64) -> Result<()> {
+        let book = &mut ctx.accounts.order_book;
+        let (side, order) = book.remove(order_id, ctx.accounts.owner.key())?;
+        ctx.accounts.open_orders.release_order(side, &order)?;
+
+        emit!(OrderCancelled {
+            order_book: book.key(),
+            owner: order.owner,
+            order_id,
+            side,
+            quantity: order.quantity,
+        });
+
+        Ok(())
+    }
+
+    /// Crank: match crossing orders at the maker's price, queueing up to `limit` fills
+    pub fn match_orders(ctx: Context<MatchOrders>, limit: u8) -> Result<()> {
+        let book = &mut ctx.accounts.order_book;
+        let book_key = book.key();
+
+        let mut matched = 0;
+        while matched < limit {
+            let Some(fill) = book.match_next()? else {
+                break;
+            };
+            matched += 1;
+
+            emit!(OrderFilled {
+                order_book: book_key,
+                buyer: fill.buyer,
+                seller: fill.seller,
+                bid_order_id: fill.bid_order_id,
+                ask_order_id: fill.ask_order_id,
+                taker_side: fill.taker_side,
+                quantity: fill.quantity,
+                discount_bps: fill.discount_bps,
+                payment: fill.payment,
+            });
+        }
+
+        require!(matched > 0, MeteraError::NoCrossingOrders);
+        Ok(())
+    }
+
+    /// Crank: credit queued fills to the traders' open-orders accounts, passed as remaining accounts.
+    /// Stops at the first fill whose buyer or seller account is missing.
+    pub fn consume_events<'info>(ctx: Context<'_, '_, 'info, 'info, ConsumeEvents<'info>>, limit: u8) -> Result<()> {
+        let book = &mut ctx.accounts.order_book;
+        let book_key = book.key();
+
+        let mut open_orders = ctx
+            .remaining_accounts
+            .iter()
+            .map(Account::<OpenOrders>::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        require!(open_orders.iter().all(|account| account.order_book == book_key), MeteraError::InvalidOpenOrders);
+        // A repeated account would be written back stale over its credited copy
+        require!(
+            open_orders.iter().enumerate().all(|(i, account)| open_orders[..i].iter().all(|other| other.key() != account.key())),
+            MeteraError::InvalidOpenOrders
+        );
+
+        let mut consumed = 0;
+        while consumed < limit && !book.fills.is_empty() {
+            let fill = book.fills[0].clone();
+            let buyer = open_orders.iter().position(|account| account.owner == fill.buyer);
+            let seller = open_orders.iter().position(|account| account.owner == fill.seller);
+            let (Some(buyer), Some(seller)) = (buyer, seller) else {
+                break;
+            };
+
+            let buyer_account = &mut open_orders[buyer];
+            buyer_account.free_vault_tokens =
+                buyer_account.free_vault_tokens.checked_add(fill.quantity).ok_or(MeteraError::ArithmeticOverflow)?;
+            buyer_account.free_payment = buyer_account.free_payment.checked_add(fill.buyer_refund).ok_or(MeteraError::ArithmeticOverflow)?;
+            let seller_account = &mut open_orders[seller];
+            seller_account.free_payment = seller_account.free_payment.checked_add(fill.payment).ok_or(MeteraError::ArithmeticOverflow)?;
+
+            book.fills.remove(0);
+            consumed += 1;
+        }
+
+        for account in &open_orders {
+            account.exit(&crate::ID)?;
+        }
+
+        emit!(FillsConsumed {
+            order_book: book_key,
+            consumed,
+            remaining: book.fills.len() as u8,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw an open-orders account's free vault tokens and payment tokens
+    pub fn settle_funds<'info>(ctx: Context<'_, '_, '_, 'info, SettleFunds<'info>>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+
+        let open_orders = &mut ctx.accounts.open_orders;
+        let vault_token_amount = open_orders.free_vault_tokens;
+        let payment_amount = open_orders.free_payment;
+
+        if vault_token_amount > 0 {
+            ctx.accounts.vault.record_incoming(&mut ctx.accounts.owner_holding, vault_token_amount)?;
+            transfer_checked_with_hook(
+                &ctx.accounts.vault_token_program,
+                &ctx.accounts.base_escrow,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.owner_token_account,
+                ctx.accounts.vault_signer.to_account_info(),
+                ctx.remaining_accounts,
+                vault_token_amount,
+                &[seeds],
+            )?;
+        }
+
+        if payment_amount > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.quote_escrow.to_account_info(),
+                        to: ctx.accounts.owner_payment_account.to_account_info(),
+                        authority: ctx.accounts.vault_signer.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                payment_amount,
+            )?;
+        }
+
+        open_orders.free_vault_tokens = 0;
+        open_orders.free_payment = 0;
+
+        emit!(FundsSettled {
+            order_book: open_orders.order_book,
+            owner: open_orders.owner,
+            vault_token_amount,
+            payment_amount,
+        });
+
+        Ok(())
+    }
+
+    // ============ RFQ ============
+
+    /// Settle a maker's signed quote against the taker in full. The transaction must verify the maker's
+    /// ed25519 signature over the Borsh-encoded `RfqQuote` in the instruction just before this one, and the
+    /// maker must have approved the RFQ authority PDA as delegate on the token account it pays from.
+    pub fn fill_rfq<'info>(ctx: Context<'_, '_, '_, 'info, FillRfq<'info>>, quote: RfqQuote) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time <= quote.expiry, MeteraError::QuoteExpired);
+        require!(quote.size > 0 && (quote.discount_bps as u64) < BPS_DENOMINATOR, MeteraError::InvalidOrder);
+        verify_ed25519_signature(&ctx.accounts.instructions_sysvar, &quote.maker, &quote.try_to_vec()?)?;
+
+        ctx.accounts.maker_investor_record.check_eligible(current_time)?;
+        ctx.accounts.taker_investor_record.check_eligible(current_time)?;
+
+        let payment = order_price(quote.size, quote.discount_bps, false)?;
+        require!(payment > 0, MeteraError::InvalidOrder);
+
+        // Both sides share one holding when the maker fills their own quote, so keep them distinct
+        require_keys_neq!(quote.maker, ctx.accounts.taker.key(), MeteraError::InvalidQuote);
+
+        let vault = &mut ctx.accounts.vault;
+        let (seller_token_account, buyer_token_account, seller_holding, buyer_holding) = match quote.side {
+            OrderSide::Ask => (
+                &ctx.accounts.maker_token_account,
+                &ctx.accounts.taker_token_account,
+                &mut ctx.accounts.maker_holding,
+                &mut ctx.accounts.taker_holding,
+            ),
+            OrderSide::Bid => (
+                &ctx.accounts.taker_token_account,
+                &ctx.accounts.maker_token_account,
+                &mut ctx.accounts.taker_holding,
+                &mut ctx.accounts.maker_holding,
+            ),
+        };
+        vault.record_outgoing(seller_holding, quote.size)?;
+        vault.record_incoming(buyer_holding, quote.size)?;
+
+        let seeds: &[&[u8]] = &[RFQ_AUTHORITY_SEED, &[ctx.bumps.rfq_authority]];
+        let (token_authority, token_seeds, payment_from, payment_to, payment_authority, payment_seeds) = match quote.side {
+            OrderSide::Ask => (
+                ctx.accounts.rfq_authority.to_account_info(),
+                &[seeds][..],
+                &ctx.accounts.taker_payment_account,
+                &ctx.accounts.maker_payment_account,
+                ctx.accounts.taker.to_account_info(),
+                &[][..],
+            ),
+            OrderSide::Bid => (
+                ctx.accounts.taker.to_account_info(),
+                &[][..],
+                &ctx.accounts.maker_payment_account,
+                &ctx.accounts.taker_payment_account,
+                ctx.accounts.rfq_authority.to_account_info(),
+                &[seeds][..],
+            ),
+        };
+
+        transfer_checked_with_hook(
+            &ctx.accounts.vault_token_program,
+            seller_token_account,
+            &ctx.accounts.token_mint,
+            buyer_token_account,
+            token_authority,
+            ctx.remaining_accounts,
+            quote.size,
+            token_seeds,
+        )?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: payment_from.to_account_info(),
+                    to: payment_to.to_account_info(),
+                    authority: payment_authority,
+                },
+                payment_seeds,
+            ),
+            payment,
+        )?;
+
+        // The nonce PDA can only be created once, so a quote cannot be filled twice
+        let rfq_nonce = &mut ctx.accounts.rfq_nonce;
+        rfq_nonce.maker = quote.maker;
+        rfq_nonce.nonce = quote.nonce;
+        rfq_nonce.taker = ctx.accounts.taker.key();
+        rfq_nonce.filled_at = current_time;
+
+        emit!(RfqFilled {
+            vault: quote.vault,
+            maker: quote.maker,
+            taker: ctx.accounts.taker.key(),
+            side: quote.side,
+            size: quote.size,
+            discount_bps: quote.discount_bps,
+            payment,
+            nonce: quote.nonce,
+        });
+
+        Ok(())
+    }
+
+    // ============ LENDING ============
+
+    /// Protocol admin opens a vault's lending reserve: its tokens are posted as collateral and its payment mint is lent.
+    /// The LTV and liquidation threshold are the limits at full health; delinquency scales them down
+    pub fn create_lending_reserve(
+        ctx: Context<CreateLendingReserve>,
+        max_ltv_bps: u16,
+        liquidation_threshold_bps: u16,
+        liquidation_bonus_bps: u16,
+        borrow_rate_bps: u16,
+    ) -> Result<()> {
+        // Collateral seized at the threshold must still cover the repayment plus the bonus
+        require!(
+            max_ltv_bps > 0
+                && max_ltv_bps <= liquidation_threshold_bps
+                && (liquidation_threshold_bps as u64) * (BPS_DENOMINATOR + liquidation_bonus_bps as u64)
+                    < BPS_DENOMINATOR * BPS_DENOMINATOR,
+            MeteraError::InvalidLendingParameters
+        );
+
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.vault = ctx.accounts.vault.key();
+        reserve.max_ltv_bps = max_ltv_bps;
+        reserve.liquidation_threshold_bps = liquidation_threshold_bps;
+        reserve.liquidation_bonus_bps = liquidation_bonus_bps;
+        reserve.borrow_rate_bps = borrow_rate_bps;
+        reserve.total_collateral = 0;
+        reserve.available_liquidity = 0;
+        reserve.total_supply_shares = 0;
+        reserve.total_borrowed_scaled = 0;
+        reserve.borrow_index = BORROW_INDEX_ONE;
+        reserve.last_accrual = Clock::get()?.unix_timestamp;
+        reserve.bump = ctx.bumps.lending_reserve;
+
+        emit!(LendingReserveCreated {
+            vault: reserve.vault,
+            lending_reserve: reserve.key(),
+            max_ltv_bps,
+            liquidation_threshold_bps,
+            liquidation_bonus_bps,
+            borrow_rate_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Supply payment tokens to a reserve for supply shares, a claim on its idle liquidity plus outstanding debt
+    pub fn supply_reserve(ctx: Context<SupplyReserve>, amount: u64) -> Result<()> {
+        require!(amount > 0, MeteraError::InvalidLendingAmount);
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.accrue(Clock::get()?.unix_timestamp)?;
+
+        let total_liquidity = reserve.total_liquidity()?;
+        let shares = if reserve.total_supply_shares == 0 || total_liquidity == 0 {
+            amount
+        } else {
+            ((amount as u128)
+                .checked_mul(reserve.total_supply_shares as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                / total_liquidity as u128) as u64
+        };
+        require!(shares > 0, MeteraError::InvalidLendingAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.lender_payment_account.to_account_info(),
+                    to: ctx.accounts.reserve_liquidity.to_account_info(),
+                    authority: ctx.accounts.lender.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        reserve.available_liquidity = reserve.available_liquidity.checked_add(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.total_supply_shares = reserve.total_supply_shares.checked_add(shares).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let deposit = &mut ctx.accounts.lending_deposit;
+        if deposit.lender == Pubkey::default() {
+            deposit.lending_reserve = reserve.key();
+            deposit.lender = ctx.accounts.lender.key();
+            deposit.bump = ctx.bumps.lending_deposit;
+        }
+        deposit.shares = deposit.shares.checked_add(shares).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(ReserveSupplied {
+            lending_reserve: reserve.key(),
+            lender: deposit.lender,
+            amount,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    /// Burn supply shares for their share of the reserve, paid from liquidity not lent out
+    pub fn withdraw_reserve(ctx: Context<WithdrawReserve>, shares: u64) -> Result<()> {
+        let deposit = &mut ctx.accounts.lending_deposit;
+        require!(shares > 0 && shares <= deposit.shares, MeteraError::InvalidLendingAmount);
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.accrue(Clock::get()?.unix_timestamp)?;
+
+        let amount = ((shares as u128)
+            .checked_mul(reserve.total_liquidity()? as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / reserve.total_supply_shares as u128) as u64;
+        require!(amount <= reserve.available_liquidity, MeteraError::InsufficientLiquidity);
+
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, reserve.vault.as_ref(), &[ctx.bumps.vault_signer]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.reserve_liquidity.to_account_info(),
+                    to: ctx.accounts.lender_payment_account.to_account_info(),
+                    authority: ctx.accounts.vault_signer.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount,
+        )?;
+
+        reserve.available_liquidity = reserve.available_liquidity.checked_sub(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.total_supply_shares = reserve.total_supply_shares.checked_sub(shares).ok_or(MeteraError::ArithmeticOverflow)?;
+        deposit.shares = deposit.shares.checked_sub(shares).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(ReserveWithdrawn {
+            lending_reserve: reserve.key(),
+            lender: deposit.lender,
+            amount,
+            shares,
+        });
+
+        Ok(())
+    }
+
+    /// Post vault tokens as collateral to the owner's obligation
+    pub fn deposit_collateral<'info>(ctx: Context<'_, '_, '_, 'info, DepositCollateral<'info>>, amount: u64) -> Result<()> {
+        require!(amount > 0, MeteraError::InvalidLendingAmount);
+        ctx.accounts.investor_record.check_eligible(Clock::get()?.unix_timestamp)?;
+
+        ctx.accounts.vault.record_outgoing(&mut ctx.accounts.owner_holding, amount)?;
+        transfer_checked_with_hook(
+            &ctx.accounts.vault_token_program,
+            &ctx.accounts.owner_token_account,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.reserve_collateral,
+            ctx.accounts.owner.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            &[],
+        )?;
+
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.total_collateral = reserve.total_collateral.checked_add(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let obligation = &mut ctx.accounts.obligation;
+        if obligation.owner == Pubkey::default() {
+            obligation.lending_reserve = reserve.key();
+            obligation.owner = ctx.accounts.owner.key();
+            obligation.bump = ctx.bumps.obligation;
+        }
+        obligation.collateral = obligation.collateral.checked_add(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(CollateralDeposited {
+            lending_reserve: reserve.key(),
+            owner: obligation.owner,
+            amount,
+            collateral: obligation.collateral,
+        });
+
+        Ok(())
+    }
+
+    /// Take collateral back, as long as the remaining collateral still supports the debt at the current LTV
+    pub fn withdraw_collateral<'info>(ctx: Context<'_, '_, '_, 'info, WithdrawCollateral<'info>>, amount: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let obligation = &mut ctx.accounts.obligation;
+        require!(amount > 0 && amount <= obligation.collateral, MeteraError::InvalidLendingAmount);
+
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.accrue(now)?;
+        let terms = reserve.collateral_terms(
+            &ctx.accounts.vault,
+            ctx.accounts.vault_treasury.amount,
+            ctx.accounts.token_mint.supply,
+            now,
+        )?;
+        let collateral = obligation.collateral.checked_sub(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        require!(
+            reserve.debt(obligation.borrowed_scaled)? <= terms.limit(collateral, terms.ltv_bps)?,
+            MeteraError::BorrowLimitExceeded
+        );
+
+        ctx.accounts.vault.record_incoming(&mut ctx.accounts.owner_holding, amount)?;
+        let vault_key = ctx.accounts.vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+        transfer_checked_with_hook(
+            &ctx.accounts.vault_token_program,
+            &ctx.accounts.reserve_collateral,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.owner_token_account,
+            ctx.accounts.vault_signer.to_account_info(),
+            ctx.remaining_accounts,
+            amount,
+            &[seeds],
+        )?;
+
+        obligation.collateral = collateral;
+        reserve.total_collateral = reserve.total_collateral.checked_sub(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(CollateralWithdrawn {
+            lending_reserve: reserve.key(),
+            owner: obligation.owner,
+            amount,
+            collateral,
+        });
+
+        Ok(())
+    }
+
+    /// Borrow payment tokens against the obligation's collateral, up to the LTV the vault's backing and delinquency allow
+    pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+        require!(amount > 0, MeteraError::InvalidLendingAmount);
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.accrue(now)?;
+        require!(amount <= reserve.available_liquidity, MeteraError::InsufficientLiquidity);
+        let terms = reserve.collateral_terms(vault, ctx.accounts.vault_treasury.amount, ctx.accounts.token_mint.supply, now)?;
+
+        // Debt is booked in index-scaled units, rounded against the borrower
+        let scaled = (amount as u128)
+            .checked_mul(BORROW_INDEX_ONE)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .div_ceil(reserve.borrow_index);
+        let obligation = &mut ctx.accounts.obligation;
+        obligation.borrowed_scaled = obligation.borrowed_scaled.checked_add(scaled).ok_or(MeteraError::ArithmeticOverflow)?;
+        let debt = reserve.debt(obligation.borrowed_scaled)?;
+        require!(debt <= terms.limit(obligation.collateral, terms.ltv_bps)?, MeteraError::BorrowLimitExceeded);
+
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, reserve.vault.as_ref(), &[ctx.bumps.vault_signer]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.reserve_liquidity.to_account_info(),
+                    to: ctx.accounts.owner_payment_account.to_account_info(),
+                    authority: ctx.accounts.vault_signer.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount,
+        )?;
+
+        reserve.available_liquidity = reserve.available_liquidity.checked_sub(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.total_borrowed_scaled = reserve.total_borrowed_scaled.checked_add(scaled).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(LoanBorrowed {
+            lending_reserve: reserve.key(),
+            owner: obligation.owner,
+            amount,
+            debt,
+        });
+
+        Ok(())
+    }
+
+    /// Repay an obligation's debt, in part or in full; anyone may repay on the owner's behalf
+    pub fn repay_borrow(ctx: Context<RepayBorrow>, amount: u64) -> Result<()> {
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.accrue(Clock::get()?.unix_timestamp)?;
+
+        let obligation = &mut ctx.accounts.obligation;
+        let debt = reserve.debt(obligation.borrowed_scaled)?;
+        let amount = amount.min(debt);
+        require!(amount > 0, MeteraError::InvalidLendingAmount);
+        let scaled = reserve.repaid_scaled(obligation.borrowed_scaled, debt, amount)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.payer_payment_account.to_account_info(),
+                    to: ctx.accounts.reserve_liquidity.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        obligation.borrowed_scaled = obligation.borrowed_scaled.checked_sub(scaled).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.total_borrowed_scaled = reserve.total_borrowed_scaled.checked_sub(scaled).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.available_liquidity = reserve.available_liquidity.checked_add(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(LoanRepaid {
+            lending_reserve: reserve.key(),
+            owner: obligation.owner,
+            payer: ctx.accounts.payer.key(),
+            amount,
+            remaining_debt: reserve.debt(obligation.borrowed_scaled)?,
+        });
+
+        Ok(())
+    }
+
+    /// Repay part of an obligation whose debt exceeds its collateral at the liquidation threshold, and seize
+    /// collateral worth the repayment plus the liquidation bonus at the vault token's backing value
+    pub fn liquidate_obligation<'info>(
+        ctx: Context<'_, '_, '_, 'info, LiquidateObligation<'info>>,
+        repay_amount: u64,
+        min_collateral_out: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.investor_record.check_eligible(now)?;
+
+        let reserve = &mut ctx.accounts.lending_reserve;
+        reserve.accrue(now)?;
+        let terms = reserve.collateral_terms(
+            &ctx.accounts.vault,
+            ctx.accounts.vault_treasury.amount,
+            ctx.accounts.token_mint.supply,
+            now,
+        )?;
+
+        let obligation = &mut ctx.accounts.obligation;
+        let debt = reserve.debt(obligation.borrowed_scaled)?;
+        require!(
+            debt > terms.limit(obligation.collateral, terms.liquidation_threshold_bps)?,
+            MeteraError::ObligationHealthy
+        );
+
+        let max_repay = (debt as u128 * LIQUIDATION_CLOSE_FACTOR_BPS as u128).div_ceil(BPS_DENOMINATOR as u128) as u64;
+        let repay_amount = repay_amount.min(max_repay);
+        require!(repay_amount > 0, MeteraError::InvalidLendingAmount);
+
+        // Unbacked collateral is worth nothing, so all of it goes to whoever repays
+        let seized = if terms.backing_bps == 0 {
+            obligation.collateral
+        } else {
+            let value = (repay_amount as u128)
+                .checked_mul(BPS_DENOMINATOR as u128 + reserve.liquidation_bonus_bps as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                / terms.backing_bps as u128;
+            value.min(obligation.collateral as u128) as u64
+        };
+        require!(seized >= min_collateral_out, MeteraError::SlippageExceeded);
+        let scaled = reserve.repaid_scaled(obligation.borrowed_scaled, debt, repay_amount)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.liquidator_payment_account.to_account_info(),
+                    to: ctx.accounts.reserve_liquidity.to_account_info(),
+                    authority: ctx.accounts.liquidator.to_account_info(),
+                },
+            ),
+            repay_amount,
+        )?;
+
+        if seized > 0 {
+            ctx.accounts.vault.record_incoming(&mut ctx.accounts.liquidator_holding, seized)?;
+            let vault_key = ctx.accounts.vault.key();
+            let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+            transfer_checked_with_hook(
+                &ctx.accounts.vault_token_program,
+                &ctx.accounts.reserve_collateral,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.liquidator_token_account,
+                ctx.accounts.vault_signer.to_account_info(),
+                ctx.remaining_accounts,
+                seized,
+                &[seeds],
+            )?;
+        }
+
+        obligation.collateral = obligation.collateral.checked_sub(seized).ok_or(MeteraError::ArithmeticOverflow)?;
+        obligation.borrowed_scaled = obligation.borrowed_scaled.checked_sub(scaled).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.total_collateral = reserve.total_collateral.checked_sub(seized).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.total_borrowed_scaled = reserve.total_borrowed_scaled.checked_sub(scaled).ok_or(MeteraError::ArithmeticOverflow)?;
+        reserve.available_liquidity = reserve.available_liquidity.checked_add(repay_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(ObligationLiquidated {
+            lending_reserve: reserve.key(),
+            owner: obligation.owner,
+            liquidator: ctx.accounts.liquidator.key(),
+            repaid: repay_amount,
+            collateral_seized: seized,
+            remaining_debt: reserve.debt(obligation.borrowed_scaled)?,
+        });
+
+        Ok(())
+    }
+
+    // ============ REPO ============
+
+    /// Seller and buyer both sign: the seller's vault tokens move into program escrow and the buyer pays the purchase price,
+    /// against the seller's right to buy them back for the repurchase price until the deadline
+    pub fn open_repo<'info>(
+        ctx: Context<'_, '_, '_, 'info, OpenRepo<'info>>,
+        repo_id: u64,
+        token_amount: u64,
+        purchase_price: u64,
+        repurchase_price: u64,
+        repurchase_deadline: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(token_amount > 0 && purchase_price > 0 && repurchase_deadline > now, MeteraError::InvalidRepoTerms);
+        require_keys_neq!(ctx.accounts.seller.key(), ctx.accounts.buyer.key(), MeteraError::InvalidRepoTerms);
+        ctx.accounts.seller_investor_record.check_eligible(now)?;
+        ctx.accounts.buyer_investor_record.check_eligible(now)?;
+
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.is_active, MeteraError::VaultInactive);
+        vault.record_outgoing(&mut ctx.accounts.seller_holding, token_amount)?;
+        transfer_checked_with_hook(
+            &ctx.accounts.vault_token_program,
+            &ctx.accounts.seller_token_account,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.repo_escrow,
+            ctx.accounts.seller.to_account_info(),
+            ctx.remaining_accounts,
+            token_amount,
+            &[],
+        )?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.buyer_payment_account.to_account_info(),
+                    to: ctx.accounts.seller_payment_account.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            ),
+            purchase_price,
+        )?;
+
+        // Only payments received from here on pass through as coupons
+        let repo = &mut ctx.accounts.repo_agreement;
+        repo.vault = vault.key();
+        repo.seller = ctx.accounts.seller.key();
+        repo.buyer = ctx.accounts.buyer.key();
+        repo.repo_id = repo_id;
+        repo.token_amount = token_amount;
+        repo.purchase_price = purchase_price;
+        repo.repurchase_price = repurchase_price;
+        repo.opened_at = now;
+        repo.repurchase_deadline = repurchase_deadline;
+        repo.next_coupon_month = vault.current_month;
+        repo.bump = ctx.bumps.repo_agreement;
+
+        emit!(RepoOpened {
+            vault: repo.vault,
+            repo_agreement: repo.key(),
+            seller: repo.seller,
+            buyer: repo.buyer,
+            token_amount,
+            purchase_price,
+            repurchase_price,
+            repurchase_deadline,
+        });
+
+        Ok(())
+    }
+
+    /// Pass a monthly payment received during the repo through to the seller: the escrow's pro-rata share of the
+    /// month's payment is redeemed at par from the escrowed tokens and, net of the redemption fee, pays down the
+    /// seller's repurchase price
+    pub fn redeem_repo_coupon(ctx: Context<RedeemRepoCoupon>) -> Result<()> {
+        let repo = &mut ctx.accounts.repo_agreement;
+        let record = &mut ctx.accounts.monthly_payment_record;
+        require!(
+            record.month >= repo.next_coupon_month && record.received_at <= repo.repurchase_deadline,
+            MeteraError::CouponNotDue
+        );
+
+        let vault = &mut ctx.accounts.vault;
+        let token_amount = (repo.token_amount as u128)
+            .checked_mul(record.amount as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(record.token_supply as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        let token_amount = token_amount.min(record.available_for_redemption);
+        require!(token_amount > 0, MeteraError::InsufficientRedemptionCapacity);
+
+        let vault_key = vault.key();
+        let seeds: &[&[u8]] = &[VAULT_SIGNER_SEED, vault_key.as_ref(), &[ctx.bumps.vault_signer]];
+        token_interface::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.vault_token_program.to_account_info(),
+                token_interface::Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.repo_escrow.to_account_info(),
+                    authority: ctx.accounts.vault_signer.to_account_info(),
+                },
+                &[seeds],
+            ),
+            token_amount,
+        )?;
+
+        let fee = FeeCharge::new(
+            FeeKind::Redemption,
+            token_amount,
+            ctx.accounts.protocol_config.protocol_fees.redemption_fee_bps,
+            vault.authority_fees.redemption_fee_bps,
+        )?;
+        pay_fee(
+            &ctx.accounts.token_program,
+            ctx.accounts.vault_treasury.to_account_info(),
+            ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.protocol_fee_account,
+            &ctx.accounts.authority_fee_account,
+            vault_key,
+            &fee,
+            &[],
+        )?;
+        let redemption_value = fee.net()?;
+
+        // The redeemed tokens leave the buyer's collateral, so their value pays down the repurchase price;
+        // anything beyond what is still owed goes to the seller
+        let paydown = redemption_value.min(repo.repurchase_price);
+        for (destination, amount) in [
+            (&ctx.accounts.buyer_payment_account, paydown),
+            (&ctx.accounts.seller_payment_account, redemption_value - paydown),
+        ] {
+            if amount > 0 {
+                token::transfer(
+                    CpiContext::new(
+                        ctx.accounts.token_program.to_account_info(),
+                        token::Transfer {
+                            from: ctx.accounts.vault_treasury.to_account_info(),
+                            to: destination.to_account_info(),
+                            authority: ctx.accounts.authority.to_account_info(),
+                        },
+                    ),
+                    amount,
+                )?;
+            }
+        }
+
+        record.available_for_redemption = record.available_for_redemption
+            .checked_sub(token_amount)
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        vault.total_redeemed = vault.total_redeemed.checked_add(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        repo.token_amount = repo.token_amount.checked_sub(token_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        repo.repurchase_price = repo.repurchase_price.saturating_sub(redemption_value);
+        repo.next_coupon_month = record.month.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        emit!(RepoCouponPassedThrough {
+            repo_agreement: repo.key(),
+            seller: repo.seller,
+            month: record.month,
+            token_amount,
+            redemption_value,
+            repurchase_price: repo.repurchase_price,
+        });
+
+        Ok(())
+    }
+
+    /// Seller buys the escrowed tokens back for the repurchase price before the deadline, closing the repo
+    pub fn repurchase_repo<'info>(ctx: Context<'_, '_, '_, 'info, RepurchaseRepo<'info>>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let repo = &ctx.accounts.repo_agreement;
+        require!(now <= repo.repurchase_deadline, MeteraError::RepoExpired);
+        ctx.accounts.seller_investor_record.check_eligible(now)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.seller_payment_account.to_account_info(),
+                    to: ctx.accounts.buyer_payment_account.to_account_info(),
+                    authority: ctx.accounts.seller.to_account_info(),
+                },
+            ),
+            repo.repurchase_price,
+        )?;
+
+        ctx.accounts.vault.record_incoming(&mut ctx.accounts.seller_holding, repo.token_amount)?;
+        release_repo_escrow(
+            &ctx.accounts.vault_token_program,
+            &ctx.accounts.repo_escrow,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.seller_token_account,
+            &ctx.accounts.vault_signer,
+            ctx.accounts.seller.to_account_info(),
+            ctx.remaining_accounts,
+            repo.token_amount,
+            &[&[VAULT_SIGNER_SEED, repo.vault.as_ref(), &[ctx.bumps.vault_signer]]],
+        )?;
+
+        emit!(RepoClosed {
+            repo_agreement: repo.key(),
+            seller: repo.seller,
+            buyer: repo.buyer,
+            token_amount: repo.token_amount,
+            repurchased: true,
+        });
+
+        Ok(())
+    }
+
+    /// Once the deadline passes without a repurchase, the buyer keeps the escrowed tokens
+    pub fn claim_repo_collateral<'info>(ctx: Context<'_, '_, '_, 'info, ClaimRepoCollateral<'info>>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let repo = &ctx.accounts.repo_agreement;
+        require!(now > repo.repurchase_deadline, MeteraError::RepoNotExpired);
+        ctx.accounts.buyer_investor_record.check_eligible(now)?;
+
+        ctx.accounts.vault.record_incoming(&mut ctx.accounts.buyer_holding, repo.token_amount)?;
+        release_repo_escrow(
+            &ctx.accounts.vault_token_program,
+            &ctx.accounts.repo_escrow,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.buyer_token_account,
+            &ctx.accounts.vault_signer,
+            ctx.accounts.seller.to_account_info(),
+            ctx.remaining_accounts,
+            repo.token_amount,
+            &[&[VAULT_SIGNER_SEED, repo.vault.as_ref(), &[ctx.bumps.vault_signer]]],
+        )?;
+
+        emit!(RepoClosed {
+            repo_agreement: repo.key(),
+            seller: repo.seller,
+            buyer: repo.buyer,
+            token_amount: repo.token_amount,
+            repurchased: false,
+        });
+
+        Ok(())
+    }
+}
+
+// ============ ACCOUNTS & STRUCTS ============
+
+#[derive(Accounts)]
+#[instruction(terms: VaultTerms, vault_name: String, symbol: String, uri: String)]
+pub struct CreateVault<'info> {
+    #[account(init, payer = authority, space = Vault::INIT_SPACE)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA holding the mint's metadata, freeze and permanent-delegate authorities; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        mint::decimals = 6,
+        mint::authority = authority,
+        mint::freeze_authority = vault_signer,
+        mint::token_program = vault_token_program,
+        extensions::transfer_hook::authority = authority,
+        extensions::transfer_hook::program_id = TRANSFER_HOOK_PROGRAM_ID,
+        extensions::metadata_pointer::authority = vault_signer,
+        extensions::metadata_pointer::metadata_address = token_mint,
+        extensions::permanent_delegate::delegate = vault_signer,
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, token_interface::Mint>>,
+
+    pub payment_mint: Account<'info, Mint>,
+
+    #[account(init, payer = authority, token::mint = payment_mint, token::authority = vault)]
+    pub vault_treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateVaultMetadata<'info> {
+    #[account(mut, has_one = authority, has_one = token_mint)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: PDA that signs for the vault mint's metadata; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MintTokens<'info> {
+    #[account(mut, has_one = token_mint, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub destination: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), destination.owner.as_ref()], bump = destination_holding.bump)]
+    pub destination_holding: Box<Account<'info, Holding>>,
+
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PurchaseTokensPrimary<'info> {
+    #[account(mut, has_one = token_mint, has_one = vault_treasury, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub vault_treasury: Account<'info, TokenAccount>,
+
+    #[account(init, payer = buyer, space = PrimarySale::INIT_SPACE)]
+    pub primary_sale: Account<'info, PrimarySale>,
+
+    #[account(mut)]
+    pub buyer_payment_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub buyer_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, has_one = vault)]
+    pub primary_offering: Option<Account<'info, PrimaryOffering>>,
+
+    #[account(mut)]
+    pub offering_escrow: Option<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, buyer.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), buyer.key().as_ref()], bump = buyer_holding.bump)]
+    pub buyer_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CreatePrimaryOffering<'info> {
+    #[account(mut, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = PrimaryOffering::INIT_SPACE,
+        seeds = [OFFERING_SEED, vault.key().as_ref(), &vault.offering_count.to_le_bytes()],
+        bump
+    )]
+    pub primary_offering: Account<'info, PrimaryOffering>,
+
+    pub payment_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [OFFERING_ESCROW_SEED, primary_offering.key().as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::authority = primary_offering
+    )]
+    pub offering_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizePrimaryOffering<'info> {
+    #[account(mut)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, has_one = vault)]
+    pub primary_offering: Account<'info, PrimaryOffering>,
+}
+
+#[derive(Accounts)]
+pub struct SettlePrimarySale<'info> {
+    #[account(mut, has_one = token_mint, has_one = vault_treasury, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(has_one = vault, constraint = primary_offering.escrow == offering_escrow.key() @ MeteraError::InvalidOfferingEscrow)]
+    pub primary_offering: Account<'info, PrimaryOffering>,
+
+    #[account(mut, has_one = vault, constraint = primary_sale.offering == primary_offering.key() @ MeteraError::SaleNotEscrowed)]
+    pub primary_sale: Account<'info, PrimarySale>,
+
+    #[account(mut)]
+    pub offering_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub buyer_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, constraint = buyer_payment_account.owner == primary_sale.buyer @ MeteraError::InvalidTokenAccountOwner)]
+    pub buyer_payment_account: Option<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RefundPrimarySale<'info> {
+    #[account(mut)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(has_one = vault, constraint = primary_offering.escrow == offering_escrow.key() @ MeteraError::InvalidOfferingEscrow)]
+    pub primary_offering: Account<'info, PrimaryOffering>,
+
+    #[account(mut, has_one = buyer, constraint = primary_sale.offering == primary_offering.key() @ MeteraError::SaleNotEscrowed)]
+    pub primary_sale: Account<'info, PrimarySale>,
+
+    #[account(mut)]
+    pub offering_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub buyer_payment_account: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), buyer.key().as_ref()], bump = buyer_holding.bump)]
+    pub buyer_holding: Box<Account<'info, Holding>>,
+
+    pub buyer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateVestingEscrow<'info> {
+    #[account(has_one = authority, has_one = token_mint)]
+    pub vault: Account<'info, Vault>,
+
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// CHECK: PDA that owns the vesting escrow; holds no data
+    #[account(seeds = [VESTING_AUTHORITY_SEED, vault.key().as_ref()], bump)]
+    pub vesting_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [VESTING_ESCROW_SEED, vault.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = vesting_authority,
+        token::token_program = token_program
+    )]
+    pub vesting_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVestedTokens<'info> {
+    #[account(mut, has_one = token_mint)]
+    pub vault: Account<'info, Vault>,
+
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, has_one = vault, has_one = buyer)]
+    pub primary_sale: Account<'info, PrimarySale>,
+
+    /// CHECK: PDA that owns the vesting escrow; holds no data
+    #[account(seeds = [VESTING_AUTHORITY_SEED, vault.key().as_ref()], bump)]
+    pub vesting_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [VESTING_ESCROW_SEED, vault.key().as_ref()], bump)]
+    pub vesting_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, constraint = buyer_token_account.owner == buyer.key() @ MeteraError::InvalidTokenAccountOwner)]
+    pub buyer_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), buyer.key().as_ref()], bump = buyer_holding.bump)]
+    pub buyer_holding: Box<Account<'info, Holding>>,
+
+    pub buyer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CreateBatchAuction<'info> {
+    #[account(mut, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = BatchAuction::INIT_SPACE,
+        seeds = [BATCH_AUCTION_SEED, vault.key().as_ref(), &vault.auction_count.to_le_bytes()],
+        bump
+    )]
+    pub batch_auction: Box<Account<'info, BatchAuction>>,
+
+    pub payment_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [BATCH_AUCTION_ESCROW_SEED, batch_auction.key().as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::authority = batch_auction
+    )]
+    pub auction_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CommitSealedBid<'info> {
+    #[account(constraint = batch_auction.escrow == auction_escrow.key() @ MeteraError::InvalidOfferingEscrow)]
+    pub batch_auction: Box<Account<'info, BatchAuction>>,
+
+    #[account(
+        init,
+        payer = bidder,
+        space = SealedBid::INIT_SPACE,
+        seeds = [SEALED_BID_SEED, batch_auction.key().as_ref(), bidder.key().as_ref()],
+        bump
+    )]
+    pub sealed_bid: Account<'info, SealedBid>,
+
+    #[account(mut)]
+    pub auction_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub bidder_payment_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, bidder.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevealSealedBid<'info> {
+    #[account(mut)]
+    pub batch_auction: Box<Account<'info, BatchAuction>>,
+
+    #[account(
+        mut,
+        has_one = bidder,
+        seeds = [SEALED_BID_SEED, batch_auction.key().as_ref(), bidder.key().as_ref()],
+        bump = sealed_bid.bump
+    )]
+    pub sealed_bid: Account<'info, SealedBid>,
+
+    pub bidder: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClearBatchAuction<'info> {
+    #[account(mut)]
+    pub batch_auction: Box<Account<'info, BatchAuction>>,
+}
+
+#[derive(Accounts)]
+pub struct SettleSealedBid<'info> {
+    #[account(mut, has_one = token_mint, has_one = vault_treasury, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(has_one = vault, constraint = batch_auction.escrow == auction_escrow.key() @ MeteraError::InvalidOfferingEscrow)]
+    pub batch_auction: Box<Account<'info, BatchAuction>>,
+
+    #[account(
+        mut,
+        seeds = [SEALED_BID_SEED, batch_auction.key().as_ref(), sealed_bid.bidder.as_ref()],
+        bump = sealed_bid.bump
+    )]
+    pub sealed_bid: Account<'info, SealedBid>,
+
+    #[account(init, payer = authority, space = PrimarySale::INIT_SPACE)]
+    pub primary_sale: Account<'info, PrimarySale>,
+
+    #[account(mut)]
+    pub auction_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub bidder_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), sealed_bid.bidder.as_ref()], bump = bidder_holding.bump)]
+    pub bidder_holding: Box<Account<'info, Holding>>,
+
+    #[account(mut, constraint = bidder_payment_account.owner == sealed_bid.bidder @ MeteraError::InvalidTokenAccountOwner)]
+    pub bidder_payment_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimSealedBid<'info> {
+    #[account(constraint = batch_auction.escrow == auction_escrow.key() @ MeteraError::InvalidOfferingEscrow)]
+    pub batch_auction: Box<Account<'info, BatchAuction>>,
+
+    #[account(
+        mut,
+        has_one = bidder,
+        seeds = [SEALED_BID_SEED, batch_auction.key().as_ref(), bidder.key().as_ref()],
+        bump = sealed_bid.bump
+    )]
+    pub sealed_bid: Account<'info, SealedBid>,
+
+    #[account(mut)]
+    pub auction_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub bidder_payment_account: Account<'info, TokenAccount>,
+
+    pub bidder: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReceiveMonthlyPayment<'info> {
+    #[account(mut)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub vault_treasury: Account<'info, TokenAccount>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(init, payer = payer, space = MonthlyPaymentRecord::INIT_SPACE)]
+    pub monthly_payment_record: Account<'info, MonthlyPaymentRecord>,
+
+    #[account(mut)]
+    pub payer_account: Account<'info, TokenAccount>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemTokens<'info> {
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub vault_mut: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub vault_treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub monthly_payment_record: Account<'info, MonthlyPaymentRecord>,
+
+    #[account(mut)]
+    pub user_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub user_payment_account: Account<'info, TokenAccount>,
+
+    #[account(init, payer = user, space = RedemptionRecord::INIT_SPACE)]
+    pub redemption_record: Account<'info, RedemptionRecord>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, user.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault_mut.key().as_ref(), user_token_account.owner.as_ref()], bump = user_holding.bump)]
+    pub user_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub vault_authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CreateLiquidityPool<'info> {
+    pub vault: Account<'info, Vault>,
+
+    #[account(init, payer = authority, space = LiquidityPool::INIT_SPACE)]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    /// LP share mint; the PDA is its own mint authority
+    #[account(
+        init,
+        payer = authority,
+        seeds = [LP_MINT_SEED, liquidity_pool.key().as_ref()],
+        bump,
+        mint::decimals = 6,
+        mint::authority = lp_mint
+    )]
+    pub lp_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(token_a_amount: u64, token_b_amount: u64, forward_discount_percentage: u8, window_number: u64, min_lp_shares: u64)]
+pub struct ProvideLiquidity<'info> {
+    #[account(mut, has_one = vault)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub lp_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub lp_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, address = liquidity_pool.lp_mint @ MeteraError::InvalidPoolMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = lp,
+        space = LPPosition::INIT_SPACE,
+        seeds = [LP_POSITION_SEED, liquidity_pool.key().as_ref(), lp.key().as_ref(), &window_number.to_le_bytes()],
+        bump
+    )]
+    pub lp_position: Box<Account<'info, LPPosition>>,
+
+    /// Holds the position's shares until its window ends
+    #[account(
+        init_if_needed,
+        payer = lp,
+        seeds = [LP_SHARE_ESCROW_SEED, lp_position.key().as_ref()],
+        bump,
+        token::mint = lp_mint,
+        token::authority = lp_position
+    )]
+    pub lp_share_escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), lp.key().as_ref()], bump = lp_holding.bump)]
+    pub lp_holding: Box<Account<'info, Holding>>,
+
+    /// Snapshotted before the deposit
+    #[account(mut, seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()], bump = price_observations.bump)]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, lp.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut)]
+    pub lp: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLiquidity<'info> {
+    #[account(mut, has_one = vault)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// Left out to withdraw shares held in the LP's own account
+    #[account(mut, has_one = lp, constraint = lp_position.pool == liquidity_pool.key() @ MeteraError::InvalidLpPosition)]
+    pub lp_position: Option<Account<'info, LPPosition>>,
+
+    #[account(mut, address = liquidity_pool.lp_mint @ MeteraError::InvalidPoolMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    /// The position's share escrow when a position is given, otherwise the LP's own share account
+    #[account(mut, token::mint = lp_mint)]
+    pub lp_share_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = lp)]
+    pub lp_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = lp)]
+    pub lp_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), lp.key().as_ref()], bump = lp_holding.bump)]
+    pub lp_holding: Box<Account<'info, Holding>>,
+
+    pub lp: Signer<'info>,
+    pub pool_authority: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(mut, has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = user)]
+    pub user_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = user)]
+    pub user_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = pool_authority)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = pool_authority)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), user.key().as_ref()], bump = user_holding.bump)]
+    pub user_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, user.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    /// Protocol fee account for the input mint
+    #[account(mut)]
+    pub protocol_fee_account: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Vault-authority fee account for the input mint
+    #[account(mut)]
+    pub authority_fee_account: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Snapshotted after the swap
+    #[account(mut, seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()], bump = price_observations.bump)]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+
+    pub user: Signer<'info>,
+    pub pool_authority: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FlashBorrow<'info> {
+    #[account(mut, has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    pub mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = mint, token::authority = pool_authority)]
+    pub pool_token_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = mint, token::authority = borrower)]
+    pub borrower_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), borrower.key().as_ref()], bump = borrower_holding.bump)]
+    pub borrower_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, borrower.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    /// CHECK: instructions sysvar, checked by address
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    pub borrower: Signer<'info>,
+    pub pool_authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    /// Must stay the first account; `flash_borrow` finds its repayment by it
+    #[account(mut, has_one = vault)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    pub mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = mint, token::authority = liquidity_pool.pool_authority)]
+    pub pool_token_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = mint, token::authority = borrower)]
+    pub borrower_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), borrower.key().as_ref()], bump = borrower_holding.bump)]
+    pub borrower_holding: Box<Account<'info, Holding>>,
+
+    pub borrower: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PoolAssistedRedeem<'info> {
+    #[account(mut, has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut, has_one = vault_treasury, has_one = authority)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint, token::authority = pool_authority)]
+    pub pool_token_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = pool_authority)]
+    pub pool_payment_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub vault_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, has_one = vault)]
+    pub monthly_payment_record: Box<Account<'info, MonthlyPaymentRecord>>,
+
+    /// Snapshotted after the redemption moves the reserves
+    #[account(mut, seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()], bump = price_observations.bump)]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    pub pool_authority: Signer<'info>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePriceObservations<'info> {
+    #[account(mut)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = PriceObservations::INIT_SPACE,
+        seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()],
+        bump
+    )]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordPriceObservation<'info> {
+    #[account(mut)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut, seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()], bump = price_observations.bump)]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+}
+
+#[derive(Accounts)]
+pub struct GetTwap<'info> {
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()], bump = price_observations.bump)]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+}
+
+/// Per hop, `remaining_accounts` holds: pool, its vault, its pool authority (signer), input mint, output mint,
+/// pool input vault, pool output vault, protocol and authority fee accounts for the input mint, the user's
+/// output token account, the input and output token programs, the user's holding in the pool's vault, and the
+/// pool's price observations
+#[derive(Accounts)]
+pub struct RouteSwap<'info> {
+    #[account(mut, token::authority = user)]
+    pub user_source: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, user.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLpFees<'info> {
+    #[account(has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, has_one = lp, constraint = lp_position.pool == liquidity_pool.key() @ MeteraError::InvalidLpPosition)]
+    pub lp_position: Account<'info, LPPosition>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = lp)]
+    pub lp_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = lp)]
+    pub lp_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = pool_authority)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = pool_authority)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), lp.key().as_ref()], bump = lp_holding.bump)]
+    pub lp_holding: Box<Account<'info, Holding>>,
+
+    pub lp: Signer<'info>,
+    pub pool_authority: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RequoteForwardDiscount<'info> {
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut, has_one = lp, constraint = lp_position.pool == liquidity_pool.key() @ MeteraError::InvalidLpPosition)]
+    pub lp_position: Account<'info, LPPosition>,
+
+    pub lp: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(window_number: u64)]
+pub struct RollLpPosition<'info> {
+    #[account(mut)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(
+        mut,
+        close = lp,
+        has_one = lp,
+        constraint = lp_position.pool == liquidity_pool.key() @ MeteraError::InvalidLpPosition
+    )]
+    pub lp_position: Account<'info, LPPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = lp,
+        space = LPPosition::INIT_SPACE,
+        seeds = [LP_POSITION_SEED, liquidity_pool.key().as_ref(), lp.key().as_ref(), &window_number.to_le_bytes()],
+        bump
+    )]
+    pub next_lp_position: Account<'info, LPPosition>,
+
+    #[account(address = liquidity_pool.lp_mint @ MeteraError::InvalidPoolMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut, seeds = [LP_SHARE_ESCROW_SEED, lp_position.key().as_ref()], bump)]
+    pub lp_share_escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = lp,
+        seeds = [LP_SHARE_ESCROW_SEED, next_lp_position.key().as_ref()],
+        bump,
+        token::mint = lp_mint,
+        token::authority = next_lp_position
+    )]
+    pub next_lp_share_escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub lp: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseLpShares<'info> {
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut, has_one = lp, constraint = lp_position.pool == liquidity_pool.key() @ MeteraError::InvalidLpPosition)]
+    pub lp_position: Account<'info, LPPosition>,
+
+    #[account(address = liquidity_pool.lp_mint @ MeteraError::InvalidPoolMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut, seeds = [LP_SHARE_ESCROW_SEED, lp_position.key().as_ref()], bump)]
+    pub lp_share_escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = lp_mint, token::authority = lp)]
+    pub lp_share_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub lp: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RampAmplification<'info> {
+    #[account(mut, has_one = pool_authority)]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    pub pool_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDiscountBook<'info> {
+    #[account(has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = pool_authority,
+        space = DiscountBook::INIT_SPACE,
+        seeds = [DISCOUNT_BOOK_SEED, liquidity_pool.key().as_ref()],
+        bump
+    )]
+    pub discount_book: Box<Account<'info, DiscountBook>>,
+
+    #[account(mut)]
+    pub pool_authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(lower_tick: u8, upper_tick: u8)]
+pub struct ProvideBandLiquidity<'info> {
+    #[account(has_one = vault)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, seeds = [DISCOUNT_BOOK_SEED, liquidity_pool.key().as_ref()], bump = discount_book.bump)]
+    pub discount_book: Box<Account<'info, DiscountBook>>,
+
+    #[account(
+        init_if_needed,
+        payer = lp,
+        space = BandPosition::INIT_SPACE,
+        seeds = [BAND_POSITION_SEED, liquidity_pool.key().as_ref(), lp.key().as_ref(), &[lower_tick], &[upper_tick]],
+        bump
+    )]
+    pub band_position: Account<'info, BandPosition>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = lp)]
+    pub lp_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = lp)]
+    pub lp_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = liquidity_pool.pool_authority)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = liquidity_pool.pool_authority)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), lp.key().as_ref()], bump = lp_holding.bump)]
+    pub lp_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, lp.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut)]
+    pub lp: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawBandLiquidity<'info> {
+    #[account(has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, seeds = [DISCOUNT_BOOK_SEED, liquidity_pool.key().as_ref()], bump = discount_book.bump)]
+    pub discount_book: Box<Account<'info, DiscountBook>>,
+
+    #[account(mut, has_one = lp, constraint = band_position.pool == liquidity_pool.key() @ MeteraError::InvalidLpPosition)]
+    pub band_position: Account<'info, BandPosition>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = lp)]
+    pub lp_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = lp)]
+    pub lp_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = pool_authority)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = pool_authority)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), lp.key().as_ref()], bump = lp_holding.bump)]
+    pub lp_holding: Box<Account<'info, Holding>>,
+
+    pub lp: Signer<'info>,
+    pub pool_authority: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SwapDiscountBands<'info> {
+    #[account(has_one = vault, has_one = pool_authority)]
+    pub liquidity_pool: Box<Account<'info, LiquidityPool>>,
+
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, seeds = [DISCOUNT_BOOK_SEED, liquidity_pool.key().as_ref()], bump = discount_book.bump)]
+    pub discount_book: Box<Account<'info, DiscountBook>>,
+
+    #[account(address = liquidity_pool.token_a_mint @ MeteraError::InvalidPoolMint)]
+    pub token_a_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = liquidity_pool.token_b_mint @ MeteraError::InvalidPoolMint)]
+    pub token_b_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = user)]
+    pub user_token_a_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = user)]
+    pub user_token_b_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_a_mint, token::authority = pool_authority)]
+    pub pool_token_a_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_b_mint, token::authority = pool_authority)]
+    pub pool_token_b_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), user.key().as_ref()], bump = user_holding.bump)]
+    pub user_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, user.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    /// Snapshotted after the swap
+    #[account(mut, seeds = [PRICE_OBSERVATIONS_SEED, liquidity_pool.key().as_ref()], bump = price_observations.bump)]
+    pub price_observations: Box<Account<'info, PriceObservations>>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    /// Protocol fee account for the input mint
+    #[account(mut)]
+    pub protocol_fee_account: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Vault-authority fee account for the input mint
+    #[account(mut)]
+    pub authority_fee_account: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub pool_authority: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocolConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = ProtocolConfig::INIT_SPACE,
+        seeds = [PROTOCOL_CONFIG_SEED],
+        bump
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ MeteraError::NotUpgradeAuthority)]
+    pub program: Program<'info, crate::program::MeteoraTokenization>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ MeteraError::NotUpgradeAuthority)]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetAttester<'info> {
+    #[account(mut, has_one = admin, seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AttestInvestor<'info> {
+    #[account(has_one = attester, seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = attester,
+        space = InvestorRecord::INIT_SPACE,
+        seeds = [INVESTOR_RECORD_SEED, wallet.key().as_ref()],
+        bump
+    )]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    /// CHECK: the wallet being attested; only its address is recorded
+    pub wallet: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub attester: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetComplianceOfficer<'info> {
+    #[account(mut, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetHoldingLimits<'info> {
+    #[account(mut, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenHolding<'info> {
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = Holding::INIT_SPACE,
+        seeds = [HOLDING_SEED, vault.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub holding: Account<'info, Holding>,
+
+    /// CHECK: the wallet whose holding is opened; only its address is recorded
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordSecondaryTransfer<'info> {
+    #[account(mut, has_one = token_mint)]
+    pub vault: Account<'info, Vault>,
+
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(token::mint = token_mint)]
+    pub source_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(token::mint = token_mint)]
+    pub destination_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), source_token_account.owner.as_ref()], bump = source_holding.bump)]
+    pub source_holding: Box<Account<'info, Holding>>,
+
+    /// Left out when the destination is one of the vault's escrows
+    #[account(
+        mut,
+        seeds = [HOLDING_SEED, vault.key().as_ref(), destination_token_account.owner.as_ref()],
+        bump = destination_holding.bump
+    )]
+    pub destination_holding: Option<Box<Account<'info, Holding>>>,
+
+    /// Only the transfer hook program can sign for this PDA
+    #[account(seeds = [HOOK_AUTHORITY_SEED], bump, seeds::program = TRANSFER_HOOK_PROGRAM_ID)]
+    pub hook_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ComplianceFreeze<'info> {
+    #[account(has_one = token_mint, has_one = compliance_officer)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: PDA holding the mint's freeze authority; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint)]
+    pub holder_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    pub compliance_officer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ForceTransfer<'info> {
+    #[account(mut, has_one = token_mint, has_one = compliance_officer)]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: PDA holding the mint's freeze and permanent-delegate authorities; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint)]
+    pub source_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_mint)]
+    pub destination_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), source_token_account.owner.as_ref()], bump = source_holding.bump)]
+    pub source_holding: Box<Account<'info, Holding>>,
+
+    #[account(
+        mut,
+        seeds = [HOLDING_SEED, vault.key().as_ref(), destination_token_account.owner.as_ref()],
+        bump = destination_holding.bump
+    )]
+    pub destination_holding: Box<Account<'info, Holding>>,
+
+    pub compliance_officer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetProtocolFees<'info> {
+    #[account(mut, has_one = admin, seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetVaultFees<'info> {
+    #[account(mut, has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateFeeAccounts<'info> {
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: PDA that owns the vault's fee accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = vault_signer,
+        token::token_program = token_program
+    )]
+    pub protocol_fee_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), mint.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = vault_signer,
+        token::token_program = token_program
+    )]
+    pub authority_fee_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns the vault's fee accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), mint.key().as_ref()], bump)]
+    pub protocol_fee_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), mint.key().as_ref()], bump)]
+    pub authority_fee_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = mint, token::authority = protocol_config.admin)]
+    pub protocol_fee_destination: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = mint, token::authority = vault.authority)]
+    pub authority_fee_destination: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CreateOrderBook<'info> {
+    #[account(has_one = authority)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns the book's escrow accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = OrderBook::INIT_SPACE,
+        seeds = [ORDER_BOOK_SEED, vault.key().as_ref()],
+        bump
+    )]
+    pub order_book: Box<Account<'info, OrderBook>>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = vault.payment_mint @ MeteraError::InvalidPoolMint)]
+    pub payment_mint: Account<'info, Mint>,
+
+    /// Vault tokens escrowed by asks
+    #[account(
+        init,
+        payer = authority,
+        seeds = [ORDER_BOOK_BASE_SEED, order_book.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = vault_signer,
+        token::token_program = vault_token_program
+    )]
+    pub base_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    /// Payment tokens escrowed by bids
+    #[account(
+        init,
+        payer = authority,
+        seeds = [ORDER_BOOK_QUOTE_SEED, order_book.key().as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::authority = vault_signer
+    )]
+    pub quote_escrow: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, seeds = [ORDER_BOOK_SEED, vault.key().as_ref()], bump = order_book.bump)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+
+    #[account(
+        init_if_needed,
+        payer = trader,
+        space = OpenOrders::INIT_SPACE,
+        seeds = [OPEN_ORDERS_SEED, order_book.key().as_ref(), trader.key().as_ref()],
+        bump
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+
+    /// Open-orders account of the worst-priced order on a full side, which the new order displaces
+    #[account(
+        mut,
+        constraint = evicted_open_orders.order_book == order_book.key() && evicted_open_orders.key() != open_orders.key()
+            @ MeteraError::InvalidOpenOrders
+    )]
+    pub evicted_open_orders: Option<Account<'info, OpenOrders>>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint)]
+    pub trader_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint)]
+    pub trader_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [ORDER_BOOK_BASE_SEED, order_book.key().as_ref()], bump)]
+    pub base_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [ORDER_BOOK_QUOTE_SEED, order_book.key().as_ref()], bump)]
+    pub quote_escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), trader.key().as_ref()], bump = trader_holding.bump)]
+    pub trader_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, trader.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+
+    #[account(mut, has_one = owner, has_one = order_book)]
+    pub open_orders: Account<'info, OpenOrders>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MatchOrders<'info> {
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+}
+
+#[derive(Accounts)]
+pub struct ConsumeEvents<'info> {
+    #[account(mut)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+}
+
+#[derive(Accounts)]
+pub struct SettleFunds<'info> {
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns the book's escrow accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(seeds = [ORDER_BOOK_SEED, vault.key().as_ref()], bump = order_book.bump)]
+    pub order_book: Box<Account<'info, OrderBook>>,
+
+    #[account(mut, has_one = owner, has_one = order_book)]
+    pub open_orders: Account<'info, OpenOrders>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint, token::authority = owner)]
+    pub owner_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = owner)]
+    pub owner_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [ORDER_BOOK_BASE_SEED, order_book.key().as_ref()], bump)]
+    pub base_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [ORDER_BOOK_QUOTE_SEED, order_book.key().as_ref()], bump)]
+    pub quote_escrow: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), owner.key().as_ref()], bump = owner_holding.bump)]
+    pub owner_holding: Box<Account<'info, Holding>>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(quote: RfqQuote)]
+pub struct FillRfq<'info> {
+    #[account(mut, constraint = vault.key() == quote.vault @ MeteraError::InvalidQuote)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint, token::authority = quote.maker)]
+    pub maker_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = quote.maker)]
+    pub maker_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = token_mint, token::authority = taker)]
+    pub taker_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = taker)]
+    pub taker_payment_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA makers approve as delegate on the accounts they pay from; holds no data
+    #[account(seeds = [RFQ_AUTHORITY_SEED], bump)]
+    pub rfq_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = taker,
+        space = RfqNonce::INIT_SPACE,
+        seeds = [RFQ_NONCE_SEED, quote.maker.as_ref(), &quote.nonce.to_le_bytes()],
+        bump
+    )]
+    pub rfq_nonce: Account<'info, RfqNonce>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, quote.maker.as_ref()], bump = maker_investor_record.bump)]
+    pub maker_investor_record: Box<Account<'info, InvestorRecord>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, taker.key().as_ref()], bump = taker_investor_record.bump)]
+    pub taker_investor_record: Box<Account<'info, InvestorRecord>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), quote.maker.as_ref()], bump = maker_holding.bump)]
+    pub maker_holding: Box<Account<'info, Holding>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), taker.key().as_ref()], bump = taker_holding.bump)]
+    pub taker_holding: Box<Account<'info, Holding>>,
+
+    /// CHECK: instructions sysvar, checked by address
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateLendingReserve<'info> {
+    #[account(has_one = admin, seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns the reserve's token accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = LendingReserve::INIT_SPACE,
+        seeds = [LENDING_RESERVE_SEED, vault.key().as_ref()],
+        bump
+    )]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(address = vault.payment_mint @ MeteraError::InvalidPoolMint)]
+    pub payment_mint: Account<'info, Mint>,
+
+    /// Vault tokens posted as collateral
+    #[account(
+        init,
+        payer = admin,
+        seeds = [RESERVE_COLLATERAL_SEED, lending_reserve.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = vault_signer,
+        token::token_program = vault_token_program
+    )]
+    pub reserve_collateral: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    /// Payment tokens supplied by lenders and not lent out
+    #[account(
+        init,
+        payer = admin,
+        seeds = [RESERVE_LIQUIDITY_SEED, lending_reserve.key().as_ref()],
+        bump,
+        token::mint = payment_mint,
+        token::authority = vault_signer
+    )]
+    pub reserve_liquidity: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SupplyReserve<'info> {
+    #[account(mut)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(
+        init_if_needed,
+        payer = lender,
+        space = LendingDeposit::INIT_SPACE,
+        seeds = [LENDING_DEPOSIT_SEED, lending_reserve.key().as_ref(), lender.key().as_ref()],
+        bump
+    )]
+    pub lending_deposit: Account<'info, LendingDeposit>,
+
+    #[account(mut, seeds = [RESERVE_LIQUIDITY_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_liquidity: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub lender_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub lender: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawReserve<'info> {
+    #[account(mut)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(mut, has_one = lender, has_one = lending_reserve)]
+    pub lending_deposit: Account<'info, LendingDeposit>,
+
+    /// CHECK: PDA that owns the reserve's token accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, lending_reserve.vault.as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [RESERVE_LIQUIDITY_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_liquidity: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub lender_payment_account: Box<Account<'info, TokenAccount>>,
+
+    pub lender: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DepositCollateral<'info> {
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(mut, has_one = vault)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = Obligation::INIT_SPACE,
+        seeds = [OBLIGATION_SEED, lending_reserve.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub obligation: Account<'info, Obligation>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint)]
+    pub owner_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [RESERVE_COLLATERAL_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_collateral: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), owner.key().as_ref()], bump = owner_holding.bump)]
+    pub owner_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, owner.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawCollateral<'info> {
+    #[account(mut, has_one = vault_treasury)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    pub vault_treasury: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA that owns the reserve's token accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, has_one = vault)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(mut, has_one = owner, has_one = lending_reserve)]
+    pub obligation: Account<'info, Obligation>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint, token::authority = owner)]
+    pub owner_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [RESERVE_COLLATERAL_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_collateral: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), owner.key().as_ref()], bump = owner_holding.bump)]
+    pub owner_holding: Box<Account<'info, Holding>>,
+
+    pub owner: Signer<'info>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Borrow<'info> {
+    #[account(has_one = vault_treasury)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    pub vault_treasury: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA that owns the reserve's token accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, has_one = vault)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(mut, has_one = owner, has_one = lending_reserve)]
+    pub obligation: Account<'info, Obligation>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, seeds = [RESERVE_LIQUIDITY_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_liquidity: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = vault.payment_mint)]
+    pub owner_payment_account: Box<Account<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RepayBorrow<'info> {
+    #[account(mut)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(mut, has_one = lending_reserve)]
+    pub obligation: Account<'info, Obligation>,
+
+    #[account(mut, seeds = [RESERVE_LIQUIDITY_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_liquidity: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer_payment_account: Box<Account<'info, TokenAccount>>,
+
+    pub payer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidateObligation<'info> {
+    #[account(mut, has_one = vault_treasury)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    pub vault_treasury: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA that owns the reserve's token accounts; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, has_one = vault)]
+    pub lending_reserve: Box<Account<'info, LendingReserve>>,
+
+    #[account(mut, has_one = lending_reserve)]
+    pub obligation: Account<'info, Obligation>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, seeds = [RESERVE_COLLATERAL_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_collateral: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [RESERVE_LIQUIDITY_SEED, lending_reserve.key().as_ref()], bump)]
+    pub reserve_liquidity: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = token_mint, token::authority = liquidator)]
+    pub liquidator_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint)]
+    pub liquidator_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), liquidator.key().as_ref()], bump = liquidator_holding.bump)]
+    pub liquidator_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, liquidator.key().as_ref()], bump = investor_record.bump)]
+    pub investor_record: Account<'info, InvestorRecord>,
+
+    pub liquidator: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(repo_id: u64)]
+pub struct OpenRepo<'info> {
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns repo escrows; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = RepoAgreement::INIT_SPACE,
+        seeds = [REPO_AGREEMENT_SEED, seller.key().as_ref(), &repo_id.to_le_bytes()],
+        bump
+    )]
+    pub repo_agreement: Box<Account<'info, RepoAgreement>>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(
+        init,
+        payer = seller,
+        seeds = [REPO_ESCROW_SEED, repo_agreement.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = vault_signer,
+        token::token_program = vault_token_program
+    )]
+    pub repo_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = token_mint)]
+    pub seller_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint)]
+    pub seller_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = vault.payment_mint)]
+    pub buyer_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), seller.key().as_ref()], bump = seller_holding.bump)]
+    pub seller_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, seller.key().as_ref()], bump = seller_investor_record.bump)]
+    pub seller_investor_record: Box<Account<'info, InvestorRecord>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, buyer.key().as_ref()], bump = buyer_investor_record.bump)]
+    pub buyer_investor_record: Box<Account<'info, InvestorRecord>>,
+
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub buyer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemRepoCoupon<'info> {
+    #[account(mut, has_one = vault_treasury, has_one = authority, has_one = token_mint)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns repo escrows; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, has_one = vault, has_one = seller)]
+    pub repo_agreement: Box<Account<'info, RepoAgreement>>,
+
+    #[account(mut, seeds = [REPO_ESCROW_SEED, repo_agreement.key().as_ref()], bump)]
+    pub repo_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub vault_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, has_one = vault)]
+    pub monthly_payment_record: Box<Account<'info, MonthlyPaymentRecord>>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = seller)]
+    pub seller_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = repo_agreement.buyer)]
+    pub buyer_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [PROTOCOL_CONFIG_SEED], bump = protocol_config.bump)]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut, seeds = [PROTOCOL_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub protocol_fee_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [AUTHORITY_FEE_SEED, vault.key().as_ref(), vault.payment_mint.as_ref()], bump)]
+    pub authority_fee_account: Box<Account<'info, TokenAccount>>,
+
+    pub seller: Signer<'info>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RepurchaseRepo<'info> {
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns repo escrows; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, has_one = vault, has_one = seller, close = seller)]
+    pub repo_agreement: Box<Account<'info, RepoAgreement>>,
+
+    #[account(mut, seeds = [REPO_ESCROW_SEED, repo_agreement.key().as_ref()], bump)]
+    pub repo_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint, token::authority = seller)]
+    pub seller_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, token::mint = vault.payment_mint)]
+    pub seller_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, token::mint = vault.payment_mint, token::authority = repo_agreement.buyer)]
+    pub buyer_payment_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), seller.key().as_ref()], bump = seller_holding.bump)]
+    pub seller_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, seller.key().as_ref()], bump = seller_investor_record.bump)]
+    pub seller_investor_record: Box<Account<'info, InvestorRecord>>,
+
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRepoCollateral<'info> {
+    #[account(mut)]
+    pub vault: Box<Account<'info, Vault>>,
+
+    /// CHECK: PDA that owns repo escrows; holds no data
+    #[account(seeds = [VAULT_SIGNER_SEED, vault.key().as_ref()], bump)]
+    pub vault_signer: UncheckedAccount<'info>,
+
+    #[account(mut, has_one = vault, has_one = seller, has_one = buyer, close = seller)]
+    pub repo_agreement: Box<Account<'info, RepoAgreement>>,
+
+    #[account(mut, seeds = [REPO_ESCROW_SEED, repo_agreement.key().as_ref()], bump)]
+    pub repo_escrow: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(address = vault.token_mint @ MeteraError::InvalidPoolMint)]
+    pub token_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut, token::mint = token_mint, token::authority = buyer)]
+    pub buyer_token_account: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    #[account(mut, seeds = [HOLDING_SEED, vault.key().as_ref(), buyer.key().as_ref()], bump = buyer_holding.bump)]
+    pub buyer_holding: Box<Account<'info, Holding>>,
+
+    #[account(seeds = [INVESTOR_RECORD_SEED, buyer.key().as_ref()], bump = buyer_investor_record.bump)]
+    pub buyer_investor_record: Box<Account<'info, InvestorRecord>>,
+
+    /// CHECK: receives the agreement's and the escrow's rent; checked against the agreement
+    #[account(mut)]
+    pub seller: UncheckedAccount<'info>,
 
-    #[account(mut)]
     pub buyer: Signer<'info>,
+    pub vault_token_program: Interface<'info, TokenInterface>,
+}
+
+#[account]
+pub struct Vault {
+    pub authority: Pubkey,
+    pub token_mint: Pubkey,
+    pub payment_mint: Pubkey,
+    pub vault_treasury: Pubkey,
+    pub compliance_officer: Pubkey,
+    pub principal: u64,
+    pub total_expected_interest: u64,
+    pub total_tokens_minted: u64,
+    pub monthly_payment: u64,
+    pub total_months: u32,
+    pub current_month: u32,
+    pub vault_name: String,
+    pub created_at: i64,
+    pub total_redeemed: u64,
+    pub is_active: bool,
+    pub offering_count: u64,
+    pub active_offering: Pubkey,
+    pub auction_count: u64,
+    pub max_holding_per_wallet: u64,
+    pub max_holder_count: u32,
+    pub holder_count: u32,
+    pub authority_fees: FeeSchedule,
+}
+
+impl Vault {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 4 + 4 + 64 + 8 + 8 + 1 + 8 + 32 + 8 + 8 + 4 + 4 + FeeSchedule::SIZE;
+
+    /// Enforce the holding limits on a wallet receiving `amount` more tokens, across all of its token accounts
+    pub fn record_incoming(&mut self, holding: &mut Holding, amount: u64) -> Result<()> {
+        let new_amount = holding.amount.checked_add(amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        require!(
+            self.max_holding_per_wallet == 0 || new_amount <= self.max_holding_per_wallet,
+            MeteraError::ExceedsMaxHolding
+        );
+
+        if holding.amount == 0 && new_amount > 0 {
+            self.holder_count = self.holder_count.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?;
+            require!(
+                self.max_holder_count == 0 || self.holder_count <= self.max_holder_count,
+                MeteraError::HolderCountExceeded
+            );
+        }
+        holding.amount = new_amount;
+        Ok(())
+    }
+
+    /// Drop a wallet from the holder count once its holding is sent out in full
+    pub fn record_outgoing(&mut self, holding: &mut Holding, amount: u64) -> Result<()> {
+        // Tokens held before the holding was opened were never added, so saturate rather than fail
+        let new_amount = holding.amount.saturating_sub(amount);
+        if holding.amount > 0 && new_amount == 0 {
+            self.holder_count = self.holder_count.saturating_sub(1);
+        }
+        holding.amount = new_amount;
+        Ok(())
+    }
+
+    /// Monthly payments that have fallen due by `now` without being received
+    pub fn missed_payments(&self, now: i64) -> u32 {
+        let months_elapsed = now.saturating_sub(self.created_at).max(0) / SECONDS_PER_MONTH;
+        let months_due = months_elapsed.min(self.total_months as i64) as u32;
+        months_due.saturating_sub(self.current_month)
+    }
+
+    /// A vault token's worth against par, in basis points: the treasury plus the payments still scheduled,
+    /// spread over the `token_supply` outstanding and capped at par
+    pub fn backing_bps(&self, treasury_balance: u64, token_supply: u64) -> Result<u64> {
+        if token_supply == 0 {
+            return Ok(BPS_DENOMINATOR);
+        }
+        let remaining_months = self.total_months.saturating_sub(self.current_month);
+        let backing = (self.monthly_payment as u128)
+            .checked_mul(remaining_months as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_add(treasury_balance as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        let backing_bps = backing
+            .checked_mul(BPS_DENOMINATOR as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / token_supply as u128;
+        Ok(backing_bps.min(BPS_DENOMINATOR as u128) as u64)
+    }
+}
+
+#[account]
+pub struct PrimarySale {
+    pub vault: Pubkey,
+    pub buyer: Pubkey,
+    pub token_amount: u64,
+    pub purchase_price: u64,
+    pub discount_percentage: u8,
+    pub purchased_at: i64,
+    pub offering: Pubkey,
+    pub status: PrimarySaleStatus,
+    pub vesting_start: i64,
+    pub cliff_duration: i64,
+    pub vesting_duration: i64,
+    pub released_amount: u64,
+}
+
+impl PrimarySale {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1 + 8 + 32 + 1 + 8 + 8 + 8 + 8;
+
+    pub fn is_vesting(&self) -> bool {
+        self.vesting_duration > 0
+    }
+
+    pub fn set_vesting(&mut self, vesting: Option<VestingTerms>, vesting_start: i64) {
+        let terms = vesting.unwrap_or(VestingTerms { cliff_duration: 0, vesting_duration: 0 });
+        self.vesting_start = vesting_start;
+        self.cliff_duration = terms.cliff_duration;
+        self.vesting_duration = terms.vesting_duration;
+        self.released_amount = 0;
+    }
+
+    /// Tokens vested at `now`: nothing before the cliff, then linear until the end of the vesting period
+    pub fn vested_amount(&self, now: i64) -> Result<u64> {
+        let elapsed = now.checked_sub(self.vesting_start).ok_or(MeteraError::ArithmeticOverflow)?;
+        if elapsed < self.cliff_duration {
+            return Ok(0);
+        }
+        if elapsed >= self.vesting_duration {
+            return Ok(self.token_amount);
+        }
+
+        let vested = (self.token_amount as u128)
+            .checked_mul(elapsed as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(self.vesting_duration as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+
+        Ok(vested)
+    }
+}
+
+/// Fee rates in basis points; the protocol and each vault authority set their own schedule
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct FeeSchedule {
+    pub origination_fee_bps: u16,
+    pub servicing_fee_bps: u16,
+    pub redemption_fee_bps: u16,
+    pub swap_fee_bps: u16,
+}
+
+impl FeeSchedule {
+    const SIZE: usize = 2 + 2 + 2 + 2;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.origination_fee_bps <= MAX_FEE_BPS
+                && self.servicing_fee_bps <= MAX_FEE_BPS
+                && self.redemption_fee_bps <= MAX_FEE_BPS
+                && self.swap_fee_bps <= MAX_FEE_BPS,
+            MeteraError::InvalidFeeSchedule
+        );
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CurveType {
+    ConstantProduct,
+    StableSwap,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FeeKind {
+    Origination,
+    Servicing,
+    Redemption,
+    Swap,
+}
+
+/// Dutch auction offering: `token_cap` tokens sold at a discount rising from the start to the max discount
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct DutchAuctionTerms {
+    pub token_cap: u64,
+    pub start_discount_percentage: u8,
+    pub max_discount_percentage: u8,
+    pub settlement: AuctionSettlement,
+}
+
+impl DutchAuctionTerms {
+    pub fn validate(&self) -> Result<()> {
+        require!(self.token_cap > 0, MeteraError::InvalidAuctionTerms);
+        require!(self.max_discount_percentage <= 100, MeteraError::InvalidDiscount);
+        require!(self.start_discount_percentage <= self.max_discount_percentage, MeteraError::InvalidAuctionTerms);
+        Ok(())
+    }
+}
+
+/// Vault tokens a wallet holds across all of its token accounts; the holding limits apply to this total
+#[account]
+pub struct Holding {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+impl Holding {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 1;
+}
+
+/// Cash flows a vault tokenizes: principal plus expected interest, paid in equal monthly installments
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct VaultTerms {
+    pub principal: u64,
+    pub total_expected_interest: u64,
+    pub monthly_payment: u64,
+    pub total_months: u32,
+}
+
+/// Cliff and linear vesting schedule for a primary purchase, in seconds from settlement
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct VestingTerms {
+    pub cliff_duration: i64,
+    pub vesting_duration: i64,
+}
+
+impl VestingTerms {
+    pub fn validate(&self) -> Result<()> {
+        require!(self.vesting_duration > 0, MeteraError::InvalidVestingTerms);
+        require!(
+            self.cliff_duration >= 0 && self.cliff_duration <= self.vesting_duration,
+            MeteraError::InvalidVestingTerms
+        );
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PrimarySaleStatus {
+    Settled,
+    Escrowed,
+    Refunded,
+}
+
+#[account]
+pub struct PrimaryOffering {
+    pub vault: Pubkey,
+    pub payment_mint: Pubkey,
+    pub escrow: Pubkey,
+    pub soft_cap: u64,
+    pub deadline: i64,
+    pub total_committed: u64,
+    pub total_tokens_sold: u64,
+    pub state: OfferingState,
+    pub created_at: i64,
+    pub bump: u8,
+    pub index: u64,
+    pub pricing: OfferingPricing,
+    pub settlement: AuctionSettlement,
+    pub token_cap: u64,
+    pub start_discount_percentage: u8,
+    pub max_discount_percentage: u8,
+    pub clearing_discount_percentage: u8,
+    /// Vesting schedule every purchase in the offering settles under
+    pub vesting: Option<VestingTerms>,
+}
+
+impl PrimaryOffering {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 1 + 8 + 1 + 8 + 1 + 1 + 8 + 1 + 1 + 1 + (1 + 16);
+
+    /// Auction discount at `now`, rising linearly from the start discount to the max discount (the floor price)
+    pub fn current_discount(&self, now: i64) -> Result<u8> {
+        if now >= self.deadline {
+            return Ok(self.max_discount_percentage);
+        }
+
+        let elapsed = now.checked_sub(self.created_at).ok_or(MeteraError::ArithmeticOverflow)?.max(0) as u128;
+        let duration = self.deadline.checked_sub(self.created_at).ok_or(MeteraError::ArithmeticOverflow)? as u128;
+        let span = self.max_discount_percentage.checked_sub(self.start_discount_percentage).ok_or(MeteraError::ArithmeticOverflow)? as u128;
+
+        let risen = span
+            .checked_mul(elapsed)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(duration)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u8;
+
+        let discount = self.start_discount_percentage.checked_add(risen).ok_or(MeteraError::ArithmeticOverflow)?;
+        Ok(discount)
+    }
+
+    pub fn is_uniform_clearing(&self) -> bool {
+        self.pricing == OfferingPricing::DutchAuction && self.settlement == AuctionSettlement::UniformClearing
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OfferingPricing {
+    FixedDiscount,
+    DutchAuction,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AuctionSettlement {
+    PayAsBid,
+    UniformClearing,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OfferingState {
+    Open,
+    Succeeded,
+    Failed,
+}
+
+#[account]
+pub struct BatchAuction {
+    pub vault: Pubkey,
+    pub payment_mint: Pubkey,
+    pub escrow: Pubkey,
+    pub token_cap: u64,
+    pub commit_deadline: i64,
+    pub reveal_deadline: i64,
+    pub settlement: AuctionSettlement,
+    pub vesting: Option<VestingTerms>,
+    pub total_revealed_demand: u64,
+    pub demand_by_discount: [u64; 101],
+    pub is_cleared: bool,
+    pub clearing_discount_percentage: u8,
+    pub marginal_allocation: u64,
+    pub marginal_demand: u64,
+    pub created_at: i64,
+    pub bump: u8,
+    pub index: u64,
+}
+
+impl BatchAuction {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 8 + 1 + (1 + 16) + 8 + 8 * 101 + 1 + 1 + 8 + 8 + 8 + 1 + 8;
+
+    /// Tokens a bid receives once the auction has cleared
+    pub fn allocation_for(&self, bid: &SealedBid) -> Result<u64> {
+        if bid.status == SealedBidStatus::Committed || bid.discount_percentage > self.clearing_discount_percentage {
+            return Ok(0);
+        }
+
+        if bid.discount_percentage < self.clearing_discount_percentage {
+            return Ok(bid.token_amount);
+        }
+
+        let allocation = (bid.token_amount as u128)
+            .checked_mul(self.marginal_allocation as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(self.marginal_demand as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+
+        Ok(allocation)
+    }
+}
+
+#[account]
+pub struct SealedBid {
+    pub auction: Pubkey,
+    pub bidder: Pubkey,
+    pub commitment: [u8; 32],
+    pub escrow_amount: u64,
+    pub token_amount: u64,
+    pub discount_percentage: u8,
+    pub status: SealedBidStatus,
+    pub bump: u8,
+}
+
+impl SealedBid {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SealedBidStatus {
+    Committed,
+    Revealed,
+    Settled,
+    Refunded,
+}
+
+#[account]
+pub struct ProtocolConfig {
+    pub admin: Pubkey,
+    pub attester: Pubkey,
+    pub protocol_fees: FeeSchedule,
+    pub bump: u8,
+}
+
+impl ProtocolConfig {
+    const INIT_SPACE: usize = 8 + 32 + 32 + FeeSchedule::SIZE + 1;
+}
+
+#[account]
+pub struct InvestorRecord {
+    pub wallet: Pubkey,
+    pub kyc_status: KycStatus,
+    pub jurisdiction: [u8; 2],
+    pub is_accredited: bool,
+    pub expires_at: i64,
+    pub attested_by: Pubkey,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl InvestorRecord {
+    const INIT_SPACE: usize = 8 + 32 + 1 + 2 + 1 + 8 + 32 + 8 + 1;
+
+    /// Require a verified, unexpired attestation
+    pub fn check_eligible(&self, now: i64) -> Result<()> {
+        require!(self.kyc_status == KycStatus::Verified, MeteraError::InvestorNotVerified);
+        require!(now < self.expires_at, MeteraError::InvestorAttestationExpired);
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum KycStatus {
+    Pending,
+    Verified,
+    Revoked,
+}
+
+#[account]
+pub struct MonthlyPaymentRecord {
+    pub vault: Pubkey,
+    pub month: u32,
+    pub amount: u64,
+    pub received_at: i64,
+    pub available_for_redemption: u64,
+    /// Token supply when the payment was received, which pro-rata shares of the payment are measured against
+    pub token_supply: u64,
+}
+
+impl MonthlyPaymentRecord {
+    const INIT_SPACE: usize = 8 + 32 + 4 + 8 + 8 + 8 + 8;
+}
+
+#[account]
+pub struct RedemptionRecord {
+    pub vault: Pubkey,
+    pub redeemer: Pubkey,
+    pub token_amount: u64,
+    pub redemption_value: u64,
+    pub redeemed_at: i64,
+    pub month: u32,
+}
+
+impl RedemptionRecord {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 8 + 4;
+}
+
+#[account]
+pub struct LiquidityPool {
+    pub vault: Pubkey,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub pool_authority: Pubkey,
+    pub pool_name: String,
+    pub created_at: i64,
+    pub token_a_reserve: u64,
+    pub token_b_reserve: u64,
+    pub total_lp_shares: u64,
+    pub window_start: i64,
+    pub window_number: u64,
+    pub is_active: bool,
+    pub lp_fee_bps: u16,
+    /// Cumulative LP fees per share in token A, Q64.64
+    pub fee_growth_a: u128,
+    /// Cumulative LP fees per share in token B, Q64.64
+    pub fee_growth_b: u128,
+    pub lp_mint: Pubkey,
+    pub lp_mint_bump: u8,
+    pub curve: CurveType,
+    /// StableSwap amplification ramps linearly from `amp_initial` to `amp_target`
+    pub amp_initial: u64,
+    pub amp_target: u64,
+    pub amp_ramp_start: i64,
+    pub amp_ramp_end: i64,
+    /// Spot prices integrated over time, Q64.64 price-seconds of A in B and of B in A; they wrap, so only differences are meaningful
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+    pub last_price_update: i64,
+    /// Outstanding flash loan within the current transaction, and which side it was drawn from
+    pub flash_loan_amount: u64,
+    pub flash_loan_token_a: bool,
+}
+
+impl LiquidityPool {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 32 + 64 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 2 + 16 + 16 + 32 + 1 + 1 + 8 + 8 + 8 + 8 + 16 + 16 + 8 + 8 + 1;
+
+    /// Spot prices of A in B and B in A, Q64.64, or `None` while either reserve is empty
+    pub fn spot_prices(&self, now: i64) -> Result<Option<(u128, u128)>> {
+        let (reserve_a, reserve_b) = (self.token_a_reserve, self.token_b_reserve);
+        if reserve_a == 0 || reserve_b == 0 {
+            return Ok(None);
+        }
+
+        let price = |reserve_in: u64, reserve_out: u64| -> Result<u128> {
+            match self.curve {
+                CurveType::ConstantProduct => Ok(((reserve_out as u128) << FEE_GROWTH_SHIFT) / reserve_in as u128),
+                CurveType::StableSwap => {
+                    let probe = (reserve_in / (BPS_DENOMINATOR / STABLE_PRICE_PROBE_BPS)).max(1);
+                    let out = stable_swap_out(self.amp_at(now)?, probe, reserve_in, reserve_out)?;
+                    Ok(((out as u128) << FEE_GROWTH_SHIFT) / probe as u128)
+                }
+            }
+        };
+        Ok(Some((price(reserve_a, reserve_b)?, price(reserve_b, reserve_a)?)))
+    }
+
+    /// Price accumulators extended to `now` at the current spot prices
+    pub fn cumulative_prices_at(&self, now: i64) -> Result<(u128, u128)> {
+        let elapsed = now.saturating_sub(self.last_price_update).max(0) as u128;
+        match self.spot_prices(now)? {
+            Some((price_a, price_b)) if elapsed > 0 => Ok((
+                self.price_a_cumulative.wrapping_add(price_a.wrapping_mul(elapsed)),
+                self.price_b_cumulative.wrapping_add(price_b.wrapping_mul(elapsed)),
+            )),
+            _ => Ok((self.price_a_cumulative, self.price_b_cumulative)),
+        }
+    }
+
+    /// Fold the time since the last update into the accumulators; call before the reserves change
+    pub fn accumulate_prices(&mut self, now: i64) -> Result<()> {
+        (self.price_a_cumulative, self.price_b_cumulative) = self.cumulative_prices_at(now)?;
+        self.last_price_update = self.last_price_update.max(now);
+        Ok(())
+    }
+
+    /// Amplification coefficient in effect at `now`
+    pub fn amp_at(&self, now: i64) -> Result<u64> {
+        if now >= self.amp_ramp_end || self.amp_ramp_end <= self.amp_ramp_start {
+            return Ok(self.amp_target);
+        }
+
+        let elapsed = now.saturating_sub(self.amp_ramp_start).max(0) as i128;
+        let duration = (self.amp_ramp_end - self.amp_ramp_start) as i128;
+        let delta = (self.amp_target as i128 - self.amp_initial as i128)
+            .checked_mul(elapsed)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / duration;
+        Ok((self.amp_initial as i128 + delta) as u64)
+    }
+
+    /// Output of a swap of `amount_in` along the pool's curve
+    pub fn swap_out(&self, amount_in: u64, reserve_in: u64, reserve_out: u64, now: i64) -> Result<u64> {
+        match self.curve {
+            CurveType::ConstantProduct => constant_product_out(amount_in, reserve_in, reserve_out),
+            CurveType::StableSwap => stable_swap_out(self.amp_at(now)?, amount_in, reserve_in, reserve_out),
+        }
+    }
+
+    /// Amounts of each token a deposit of at most `token_a_max` and `token_b_max` takes, and the shares it creates
+    pub fn deposit_quote(&self, token_a_max: u64, token_b_max: u64, now: i64) -> Result<(u64, u64, u64)> {
+        if self.total_lp_shares == 0 {
+            let shares = match self.curve {
+                CurveType::ConstantProduct => (token_a_max as u128)
+                    .checked_mul(token_b_max as u128)
+                    .ok_or(MeteraError::ArithmeticOverflow)?
+                    .isqrt() as u64,
+                CurveType::StableSwap => {
+                    let d = stable_swap_d(self.amp_at(now)?, token_a_max, token_b_max)?;
+                    u64::try_from(d).map_err(|_| MeteraError::ArithmeticOverflow)?
+                }
+            };
+            require!(shares > MINIMUM_LIQUIDITY, MeteraError::InsufficientInitialLiquidity);
+            return Ok((token_a_max, token_b_max, shares));
+        }
+
+        let proportional = |amount: u64, numerator: u64, denominator: u64| -> Result<u64> {
+            Ok((amount as u128)
+                .checked_mul(numerator as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                .checked_div(denominator as u128)
+                .ok_or(MeteraError::ZeroLiquidityPool)? as u64)
+        };
+
+        // Take all of one side and the matching amount of the other at the current reserve ratio
+        let token_b_optimal = proportional(token_a_max, self.token_b_reserve, self.token_a_reserve)?;
+        let (token_a_amount, token_b_amount) = if token_b_optimal <= token_b_max {
+            (token_a_max, token_b_optimal)
+        } else {
+            (proportional(token_b_max, self.token_a_reserve, self.token_b_reserve)?, token_b_max)
+        };
+
+        let shares = match self.curve {
+            CurveType::ConstantProduct => proportional(token_a_amount, self.total_lp_shares, self.token_a_reserve)?
+                .min(proportional(token_b_amount, self.total_lp_shares, self.token_b_reserve)?),
+            CurveType::StableSwap => {
+                // Shares grow with the invariant
+                let amp = self.amp_at(now)?;
+                let d0 = stable_swap_d(amp, self.token_a_reserve, self.token_b_reserve)?;
+                let d1 = stable_swap_d(
+                    amp,
+                    self.token_a_reserve.checked_add(token_a_amount).ok_or(MeteraError::ArithmeticOverflow)?,
+                    self.token_b_reserve.checked_add(token_b_amount).ok_or(MeteraError::ArithmeticOverflow)?,
+                )?;
+                (self.total_lp_shares as u128)
+                    .checked_mul(d1.saturating_sub(d0))
+                    .ok_or(MeteraError::ArithmeticOverflow)?
+                    .checked_div(d0)
+                    .ok_or(MeteraError::ZeroLiquidityPool)? as u64
+            }
+        };
+        Ok((token_a_amount, token_b_amount, shares))
+    }
+
+    /// Start of a commitment window
+    pub fn window_start_of(&self, window_number: u64) -> Result<i64> {
+        let offset = (window_number as i64).checked_mul(LP_WINDOW_DURATION).ok_or(MeteraError::ArithmeticOverflow)?;
+        Ok(self.created_at.checked_add(offset).ok_or(MeteraError::ArithmeticOverflow)?)
+    }
+
+    /// End of a commitment window, when its liquidity unlocks
+    pub fn window_end(&self, window_number: u64) -> Result<i64> {
+        self.window_start_of(window_number.checked_add(1).ok_or(MeteraError::ArithmeticOverflow)?)
+    }
+
+    /// Move `window_number` and `window_start` to the window containing `now`
+    pub fn sync_window(&mut self, now: i64) -> Result<u64> {
+        let elapsed = now.checked_sub(self.created_at).ok_or(MeteraError::ArithmeticOverflow)?.max(0);
+        self.window_number = (elapsed / LP_WINDOW_DURATION) as u64;
+        self.window_start = self.window_start_of(self.window_number)?;
+        Ok(self.window_number)
+    }
+
+    /// Monthly quote period of `window_number` that `now` falls in
+    pub fn quote_period(&self, window_number: u64, now: i64) -> Result<u64> {
+        let elapsed = now.checked_sub(self.window_start_of(window_number)?).ok_or(MeteraError::ArithmeticOverflow)?.max(0);
+        Ok((elapsed / LP_QUOTE_PERIOD) as u64)
+    }
+}
+
+#[account]
+pub struct LPPosition {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub token_a_amount: u64,
+    pub token_b_amount: u64,
+    pub lp_shares: u64,
+    pub forward_discount_percentage: u8,
+    pub window_number: u64,
+    pub provided_at: i64,
+    pub fee_growth_checkpoint_a: u128,
+    pub fee_growth_checkpoint_b: u128,
+    pub fees_owed_a: u64,
+    pub fees_owed_b: u64,
+    pub bump: u8,
+    /// Monthly period of the window in which the forward discount was last quoted
+    pub quote_period: u64,
+}
+
+impl LPPosition {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 8 + 1 + 8 + 8 + 16 + 16 + 8 + 8 + 1 + 8;
+
+    /// Fill in a freshly created position PDA; existing positions are left alone
+    pub fn open_if_new(&mut self, pool: &LiquidityPool, pool_key: Pubkey, lp: Pubkey, window_number: u64, now: i64, bump: u8) -> Result<()> {
+        if self.pool != Pubkey::default() {
+            return Ok(());
+        }
+
+        self.pool = pool_key;
+        self.lp = lp;
+        self.window_number = window_number;
+        self.provided_at = now;
+        self.fee_growth_checkpoint_a = pool.fee_growth_a;
+        self.fee_growth_checkpoint_b = pool.fee_growth_b;
+        self.bump = bump;
+        self.quote_period = pool.quote_period(window_number, now)?;
+        Ok(())
+    }
+
+    /// Top up the position, averaging the forward discount by shares
+    pub fn add_deposit(&mut self, token_a_amount: u64, token_b_amount: u64, lp_shares: u64, forward_discount_percentage: u8) -> Result<()> {
+        let total_shares = self.lp_shares.checked_add(lp_shares).ok_or(MeteraError::ArithmeticOverflow)?;
+        if total_shares > 0 {
+            let weighted_discount = (self.forward_discount_percentage as u128)
+                .checked_mul(self.lp_shares as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                .checked_add((forward_discount_percentage as u128).checked_mul(lp_shares as u128).ok_or(MeteraError::ArithmeticOverflow)?)
+                .ok_or(MeteraError::ArithmeticOverflow)?;
+            self.forward_discount_percentage = weighted_discount
+                .checked_div(total_shares as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)? as u8;
+        }
+
+        self.token_a_amount = self.token_a_amount.checked_add(token_a_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.token_b_amount = self.token_b_amount.checked_add(token_b_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.lp_shares = total_shares;
+        Ok(())
+    }
+
+    /// Drop withdrawn shares and release the same fraction of the entry amounts
+    pub fn remove_shares(&mut self, lp_shares: u64) -> Result<()> {
+        // Shares sent into the escrow from outside can exceed what the position recorded
+        let removed = lp_shares.min(self.lp_shares);
+        if removed == 0 {
+            return Ok(());
+        }
+
+        let release = |amount: u64| -> Result<u64> {
+            Ok((amount as u128)
+                .checked_mul(removed as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                .checked_div(self.lp_shares as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)? as u64)
+        };
+        let released_a = release(self.token_a_amount)?;
+        let released_b = release(self.token_b_amount)?;
+
+        self.token_a_amount = self.token_a_amount.checked_sub(released_a).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.token_b_amount = self.token_b_amount.checked_sub(released_b).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.lp_shares = self.lp_shares.checked_sub(removed).ok_or(MeteraError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Move fees earned since the last checkpoint into `fees_owed_*` and advance the checkpoint
+    pub fn accrue_fees(&mut self, fee_growth_a: u128, fee_growth_b: u128) -> Result<()> {
+        let earned = |growth: u128, checkpoint: u128| -> Result<u64> {
+            Ok((growth
+                .checked_sub(checkpoint)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                .checked_mul(self.lp_shares as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                >> FEE_GROWTH_SHIFT) as u64)
+        };
+
+        let earned_a = earned(fee_growth_a, self.fee_growth_checkpoint_a)?;
+        let earned_b = earned(fee_growth_b, self.fee_growth_checkpoint_b)?;
+        self.fees_owed_a = self.fees_owed_a.checked_add(earned_a).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.fees_owed_b = self.fees_owed_b.checked_add(earned_b).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.fee_growth_checkpoint_a = fee_growth_a;
+        self.fee_growth_checkpoint_b = fee_growth_b;
+        Ok(())
+    }
+}
+
+/// Buy-side liquidity for a pool quoted in whole-percent discount ticks, token A being the vault token
+/// and token B its payment mint
+#[account]
+pub struct DiscountBook {
+    pub pool: Pubkey,
+    /// Ticks below the current one have filled into vault tokens; ticks above it still hold payment tokens
+    pub current_tick: u8,
+    /// Share of the current tick's liquidity that has filled, Q64.64
+    pub current_fill: u128,
+    /// Payment tokens quoted at each tick by all bands covering it
+    pub tick_liquidity: [u64; DISCOUNT_TICKS],
+    pub tick_crossings: u64,
+    pub vault_tokens_held: u64,
+    pub payment_held: u64,
+    pub bump: u8,
+}
+
+impl DiscountBook {
+    const INIT_SPACE: usize = 8 + 32 + 1 + 16 + 8 * DISCOUNT_TICKS + 8 + 8 + 8 + 1;
+
+    /// Vault and payment tokens backing `liquidity` per tick across a band; deposits round up, withdrawals down
+    pub fn band_amounts(&self, lower_tick: u8, upper_tick: u8, liquidity: u64, round_up: bool) -> Result<(u64, u64)> {
+        let liquidity = liquidity as u128;
+        let (mut vault_tokens, mut payment) = (0u128, 0u128);
+        for tick in lower_tick..=upper_tick {
+            let fill = match tick.cmp(&self.current_tick) {
+                std::cmp::Ordering::Less => TICK_FILL_ONE,
+                std::cmp::Ordering::Equal => self.current_fill,
+                std::cmp::Ordering::Greater => 0,
+            };
+            let filled_down = fill_amount(liquidity, fill, false)?;
+            let filled_up = fill_amount(liquidity, fill, true)?;
+            let (filled, unfilled) = if round_up {
+                (filled_up, liquidity - filled_down)
+            } else {
+                (filled_down, liquidity - filled_up)
+            };
+
+            vault_tokens = vault_tokens.checked_add(tick_vault_tokens(filled, tick, round_up)?).ok_or(MeteraError::ArithmeticOverflow)?;
+            payment = payment.checked_add(unfilled).ok_or(MeteraError::ArithmeticOverflow)?;
+        }
+
+        Ok((
+            u64::try_from(vault_tokens).map_err(|_| MeteraError::ArithmeticOverflow)?,
+            u64::try_from(payment).map_err(|_| MeteraError::ArithmeticOverflow)?,
+        ))
+    }
+
+    /// Add or remove `liquidity` at every tick of a band
+    pub fn update_band(&mut self, lower_tick: u8, upper_tick: u8, liquidity: u64, add: bool) -> Result<()> {
+        for tick in lower_tick..=upper_tick {
+            let tick_liquidity = &mut self.tick_liquidity[tick as usize];
+            *tick_liquidity = if add {
+                tick_liquidity.checked_add(liquidity)
+            } else {
+                tick_liquidity.checked_sub(liquidity)
+            }
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        }
+        Ok(())
+    }
+
+    /// Fill bands from the current tick toward deeper discounts with `amount_in` vault tokens;
+    /// returns the payment tokens paid out and the ticks crossed
+    pub fn sell_vault_tokens(&mut self, amount_in: u64) -> Result<(u64, u32)> {
+        let mut remaining = amount_in as u128;
+        let mut amount_out = 0u128;
+        let mut ticks_crossed = 0u32;
+
+        loop {
+            let tick = self.current_tick;
+            let liquidity = self.tick_liquidity[tick as usize] as u128;
+            if liquidity > 0 && self.current_fill < TICK_FILL_ONE {
+                let unfilled = liquidity - fill_amount(liquidity, self.current_fill, true)?;
+                let to_fill = tick_vault_tokens(unfilled, tick, true)?;
+                if remaining < to_fill {
+                    let paid = tick_payment(remaining, tick)?;
+                    let fill = paid
+                        .checked_shl(FEE_GROWTH_SHIFT)
+                        .ok_or(MeteraError::ArithmeticOverflow)?
+                        .div_ceil(liquidity);
+                    self.current_fill = self.current_fill.saturating_add(fill).min(TICK_FILL_ONE);
+                    amount_out = amount_out.checked_add(paid).ok_or(MeteraError::ArithmeticOverflow)?;
+                    remaining = 0;
+                    break;
+                }
+
+                amount_out = amount_out.checked_add(unfilled).ok_or(MeteraError::ArithmeticOverflow)?;
+                remaining -= to_fill;
+                self.current_fill = TICK_FILL_ONE;
+            }
+
+            if remaining == 0 || tick as usize + 1 == DISCOUNT_TICKS {
+                break;
+            }
+            self.current_tick = tick + 1;
+            self.current_fill = 0;
+            ticks_crossed += 1;
+        }
+
+        require!(remaining == 0, MeteraError::InsufficientLiquidity);
+        Ok((u64::try_from(amount_out).map_err(|_| MeteraError::ArithmeticOverflow)?, ticks_crossed))
+    }
+
+    /// Buy filled vault tokens from the current tick toward par with `amount_in` payment tokens;
+    /// returns the vault tokens paid out and the ticks crossed
+    pub fn buy_vault_tokens(&mut self, amount_in: u64) -> Result<(u64, u32)> {
+        let mut remaining = amount_in as u128;
+        let mut amount_out = 0u128;
+        let mut ticks_crossed = 0u32;
+
+        loop {
+            let tick = self.current_tick;
+            let liquidity = self.tick_liquidity[tick as usize] as u128;
+            if liquidity > 0 && self.current_fill > 0 {
+                let filled = fill_amount(liquidity, self.current_fill, false)?;
+                if remaining < filled {
+                    let unfill = remaining
+                        .checked_shl(FEE_GROWTH_SHIFT)
+                        .ok_or(MeteraError::ArithmeticOverflow)?
+                        .div_ceil(liquidity);
+                    self.current_fill = self.current_fill.saturating_sub(unfill);
+                    amount_out = amount_out.checked_add(tick_vault_tokens(remaining, tick, false)?).ok_or(MeteraError::ArithmeticOverflow)?;
+                    remaining = 0;
+                    break;
+                }
+
+                amount_out = amount_out.checked_add(tick_vault_tokens(filled, tick, false)?).ok_or(MeteraError::ArithmeticOverflow)?;
+                remaining -= filled;
+                self.current_fill = 0;
+            }
+
+            if remaining == 0 || tick == 0 {
+                break;
+            }
+            self.current_tick = tick - 1;
+            self.current_fill = TICK_FILL_ONE;
+            ticks_crossed += 1;
+        }
+
+        require!(remaining == 0, MeteraError::InsufficientLiquidity);
+        Ok((u64::try_from(amount_out).map_err(|_| MeteraError::ArithmeticOverflow)?, ticks_crossed))
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PriceObservation {
+    pub timestamp: i64,
+    pub price_a_cumulative: u128,
+    pub price_b_cumulative: u128,
+}
+
+impl PriceObservation {
+    const SIZE: usize = 8 + 16 + 16;
+}
+
+/// Ring buffer of a pool's price accumulator snapshots; `head` is the newest
+#[account]
+pub struct PriceObservations {
+    pub pool: Pubkey,
+    pub head: u16,
+    pub observations: Vec<PriceObservation>,
+    pub bump: u8,
+}
+
+impl PriceObservations {
+    const INIT_SPACE: usize = 8 + 32 + 2 + 4 + PriceObservation::SIZE * MAX_PRICE_OBSERVATIONS + 1;
+
+    /// Snapshot the pool's accumulators unless the newest observation is within the spacing interval
+    pub fn record(&mut self, pool: &LiquidityPool, now: i64) -> Result<()> {
+        if let Some(newest) = self.observations.get(self.head as usize) {
+            if now < newest.timestamp.saturating_add(PRICE_OBSERVATION_SPACING) {
+                return Ok(());
+            }
+        }
+
+        let (price_a_cumulative, price_b_cumulative) = pool.cumulative_prices_at(now)?;
+        let observation = PriceObservation {
+            timestamp: now,
+            price_a_cumulative,
+            price_b_cumulative,
+        };
+        if self.observations.is_empty() {
+            self.observations.push(observation);
+        } else if self.observations.len() < MAX_PRICE_OBSERVATIONS {
+            self.observations.push(observation);
+            self.head = (self.observations.len() - 1) as u16;
+        } else {
+            self.head = ((self.head as usize + 1) % MAX_PRICE_OBSERVATIONS) as u16;
+            self.observations[self.head as usize] = observation;
+        }
+        Ok(())
+    }
+
+    /// Newest observation taken at or before `timestamp`
+    pub fn at_or_before(&self, timestamp: i64) -> Option<&PriceObservation> {
+        self.observations
+            .iter()
+            .filter(|observation| observation.timestamp <= timestamp)
+            .max_by_key(|observation| observation.timestamp)
+    }
+}
+
+/// Time-weighted average prices returned by `get_twap`, Q64.64
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Twap {
+    pub price_a: u128,
+    pub price_b: u128,
+    pub start: i64,
+    pub end: i64,
+}
+
+/// An LP's liquidity across one band of discount ticks
+#[account]
+pub struct BandPosition {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lower_tick: u8,
+    pub upper_tick: u8,
+    /// Payment tokens quoted at each tick of the band
+    pub liquidity: u64,
+    pub bump: u8,
+}
+
+impl BandPosition {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 1 + 1 + 8 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OrderSide {
+    Bid,
+    Ask,
+}
+
+/// A resting limit order; `escrow` is what remains locked for it (payment tokens for bids, vault tokens for asks)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Order {
+    pub owner: Pubkey,
+    pub order_id: u64,
+    pub discount_bps: u16,
+    pub quantity: u64,
+    pub escrow: u64,
+}
+
+impl Order {
+    const SIZE: usize = 32 + 8 + 2 + 8 + 8;
+}
+
+/// A matched trade awaiting the consume crank; the maker is the older of the two orders
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Fill {
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub bid_order_id: u64,
+    pub ask_order_id: u64,
+    pub taker_side: OrderSide,
+    pub quantity: u64,
+    pub discount_bps: u16,
+    /// Payment tokens owed to the seller
+    pub payment: u64,
+    /// Bid escrow released back to the buyer when the fill beats the bid price
+    pub buyer_refund: u64,
+}
+
+impl Fill {
+    const SIZE: usize = 32 + 32 + 8 + 8 + 1 + 8 + 2 + 8 + 8;
+}
+
+/// Central limit order book for a vault token, bids sorted by lowest discount and asks by highest, each in time priority
+#[account]
+pub struct OrderBook {
+    pub vault: Pubkey,
+    pub next_order_id: u64,
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+    pub fills: Vec<Fill>,
+    pub bump: u8,
+}
+
+impl OrderBook {
+    const INIT_SPACE: usize =
+        8 + 32 + 8 + 4 + Order::SIZE * MAX_ORDERS_PER_SIDE + 4 + Order::SIZE * MAX_ORDERS_PER_SIDE + 4 + Fill::SIZE * MAX_FILL_EVENTS + 1;
+
+    /// Rest an order behind every order at the same or a better price. On a full side the order must beat the
+    /// worst-priced resting order, which it displaces and returns.
+    pub fn insert(&mut self, side: OrderSide, order: Order) -> Result<Option<Order>> {
+        let orders = match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+
+        let index = orders
+            .iter()
+            .position(|resting| match side {
+                OrderSide::Bid => resting.discount_bps > order.discount_bps,
+                OrderSide::Ask => resting.discount_bps < order.discount_bps,
+            })
+            .unwrap_or(orders.len());
+        let evicted = if orders.len() < MAX_ORDERS_PER_SIDE {
+            None
+        } else {
+            // The last order is the worst priced, and the newest among equals
+            require!(index < orders.len(), MeteraError::OrderBookFull);
+            orders.pop()
+        };
+        orders.insert(index, order);
+        Ok(evicted)
+    }
+
+    /// Take `owner`'s order off whichever side it rests on
+    pub fn remove(&mut self, order_id: u64, owner: Pubkey) -> Result<(OrderSide, Order)> {
+        for (side, orders) in [(OrderSide::Bid, &mut self.bids), (OrderSide::Ask, &mut self.asks)] {
+            if let Some(index) = orders.iter().position(|order| order.order_id == order_id) {
+                require_keys_eq!(orders[index].owner, owner, MeteraError::OrderNotFound);
+                return Ok((side, orders.remove(index)));
+            }
+        }
+        err!(MeteraError::OrderNotFound)
+    }
+
+    /// Match the best bid against the best ask if they cross and the fill queue has room
+    pub fn match_next(&mut self) -> Result<Option<Fill>> {
+        if self.fills.len() >= MAX_FILL_EVENTS {
+            return Ok(None);
+        }
+        let (Some(bid), Some(ask)) = (self.bids.first(), self.asks.first()) else {
+            return Ok(None);
+        };
+        if bid.discount_bps > ask.discount_bps {
+            return Ok(None);
+        }
+
+        let (taker_side, discount_bps) = if bid.order_id < ask.order_id {
+            (OrderSide::Ask, bid.discount_bps)
+        } else {
+            (OrderSide::Bid, ask.discount_bps)
+        };
+        let quantity = bid.quantity.min(ask.quantity);
+        let payment = order_price(quantity, discount_bps, false)?;
+        // A bid filling in full releases all of its escrow, so rounding never strands payment tokens
+        let bid_released = if quantity == bid.quantity {
+            bid.escrow
+        } else {
+            order_price(quantity, bid.discount_bps, true)?.min(bid.escrow)
+        };
+        let fill = Fill {
+            buyer: bid.owner,
+            seller: ask.owner,
+            bid_order_id: bid.order_id,
+            ask_order_id: ask.order_id,
+            taker_side,
+            quantity,
+            discount_bps,
+            payment,
+            buyer_refund: bid_released.checked_sub(payment).ok_or(MeteraError::ArithmeticOverflow)?,
+        };
+
+        let bid = &mut self.bids[0];
+        bid.quantity -= quantity;
+        bid.escrow -= bid_released;
+        if bid.quantity == 0 {
+            self.bids.remove(0);
+        }
+        let ask = &mut self.asks[0];
+        ask.quantity -= quantity;
+        ask.escrow -= quantity;
+        if ask.quantity == 0 {
+            self.asks.remove(0);
+        }
+
+        self.fills.push(fill.clone());
+        Ok(Some(fill))
+    }
+}
+
+/// A trader's settled but unwithdrawn balances on one order book
+#[account]
+pub struct OpenOrders {
+    pub owner: Pubkey,
+    pub order_book: Pubkey,
+    pub free_vault_tokens: u64,
+    pub free_payment: u64,
+    pub bump: u8,
+}
+
+impl OpenOrders {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1;
+
+    /// Return a resting order's unfilled escrow to the free balances
+    pub fn release_order(&mut self, side: OrderSide, order: &Order) -> Result<()> {
+        match side {
+            OrderSide::Bid => {
+                self.free_payment = self.free_payment.checked_add(order.escrow).ok_or(MeteraError::ArithmeticOverflow)?;
+            }
+            OrderSide::Ask => {
+                self.free_vault_tokens = self.free_vault_tokens.checked_add(order.quantity).ok_or(MeteraError::ArithmeticOverflow)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Terms a maker signs off-chain; `side` is the maker's side and `size` is in vault tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RfqQuote {
+    pub vault: Pubkey,
+    pub maker: Pubkey,
+    pub side: OrderSide,
+    pub size: u64,
+    pub discount_bps: u16,
+    pub expiry: i64,
+    pub nonce: u64,
+}
+
+/// Marks a maker's quote nonce as used
+#[account]
+pub struct RfqNonce {
+    pub maker: Pubkey,
+    pub nonce: u64,
+    pub taker: Pubkey,
+    pub filled_at: i64,
+}
+
+impl RfqNonce {
+    const INIT_SPACE: usize = 8 + 32 + 8 + 32 + 8;
+}
+
+#[account]
+pub struct LendingReserve {
+    pub vault: Pubkey,
+    /// Limits at full health, in basis points of the collateral's backing value
+    pub max_ltv_bps: u16,
+    pub liquidation_threshold_bps: u16,
+    pub liquidation_bonus_bps: u16,
+    /// Annual borrow rate, compounded into the borrow index at each accrual
+    pub borrow_rate_bps: u16,
+    pub total_collateral: u64,
+    pub available_liquidity: u64,
+    pub total_supply_shares: u64,
+    /// Debt in index-scaled units; debt owed is scaled units times the borrow index
+    pub total_borrowed_scaled: u128,
+    pub borrow_index: u128,
+    pub last_accrual: i64,
+    pub bump: u8,
+}
+
+impl LendingReserve {
+    const INIT_SPACE: usize = 8 + 32 + 2 + 2 + 2 + 2 + 8 + 8 + 8 + 16 + 16 + 8 + 1;
+
+    /// Grow the borrow index by the interest accrued since the last accrual
+    pub fn accrue(&mut self, now: i64) -> Result<()> {
+        let elapsed = now.saturating_sub(self.last_accrual);
+        if elapsed <= 0 {
+            return Ok(());
+        }
+        let growth = self.borrow_index
+            .checked_mul(self.borrow_rate_bps as u128 * elapsed as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / (BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128);
+        self.borrow_index = self.borrow_index.checked_add(growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        self.last_accrual = now;
+        Ok(())
+    }
+
+    /// Debt owed on `scaled` units at the current index, rounded up
+    pub fn debt(&self, scaled: u128) -> Result<u64> {
+        let debt = scaled
+            .checked_mul(self.borrow_index)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .div_ceil(BORROW_INDEX_ONE);
+        u64::try_from(debt).map_err(|_| error!(MeteraError::ArithmeticOverflow))
+    }
+
+    /// Scaled units a repayment of `amount` against `debt` retires; paying the whole debt clears them all
+    fn repaid_scaled(&self, scaled: u128, debt: u64, amount: u64) -> Result<u128> {
+        if amount >= debt {
+            return Ok(scaled);
+        }
+        let repaid = (amount as u128)
+            .checked_mul(BORROW_INDEX_ONE)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / self.borrow_index;
+        Ok(repaid.min(scaled))
+    }
+
+    /// Idle liquidity plus outstanding debt (rounded down), the amount supply shares claim
+    pub fn total_liquidity(&self) -> Result<u64> {
+        let borrowed = self.total_borrowed_scaled
+            .checked_mul(self.borrow_index)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / BORROW_INDEX_ONE;
+        u64::try_from(borrowed)
+            .ok()
+            .and_then(|borrowed| borrowed.checked_add(self.available_liquidity))
+            .ok_or(error!(MeteraError::ArithmeticOverflow))
+    }
+
+    /// The reserve's limits as they stand for its vault at `now`; each missed payment takes a haircut off both ratios
+    pub fn collateral_terms(&self, vault: &Vault, treasury_balance: u64, token_supply: u64, now: i64) -> Result<CollateralTerms> {
+        let health_bps = BPS_DENOMINATOR
+            .saturating_sub((vault.missed_payments(now) as u64).saturating_mul(DELINQUENCY_HAIRCUT_BPS));
+        Ok(CollateralTerms {
+            backing_bps: vault.backing_bps(treasury_balance, token_supply)?,
+            ltv_bps: self.max_ltv_bps as u64 * health_bps / BPS_DENOMINATOR,
+            liquidation_threshold_bps: self.liquidation_threshold_bps as u64 * health_bps / BPS_DENOMINATOR,
+        })
+    }
+}
+
+/// A lending reserve's collateral valuation and limits for its vault's current schedule and delinquency
+pub struct CollateralTerms {
+    pub backing_bps: u64,
+    pub ltv_bps: u64,
+    pub liquidation_threshold_bps: u64,
+}
+
+impl CollateralTerms {
+    /// Payment tokens `collateral` vault tokens support at `ratio_bps` of their backing value
+    pub fn limit(&self, collateral: u64, ratio_bps: u64) -> Result<u64> {
+        let limit = (collateral as u128)
+            .checked_mul(self.backing_bps as u128 * ratio_bps as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            / (BPS_DENOMINATOR as u128 * BPS_DENOMINATOR as u128);
+        Ok(limit as u64)
+    }
+}
+
+/// A lender's supply shares in a lending reserve
+#[account]
+pub struct LendingDeposit {
+    pub lending_reserve: Pubkey,
+    pub lender: Pubkey,
+    pub shares: u64,
+    pub bump: u8,
+}
+
+impl LendingDeposit {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 1;
+}
+
+/// A borrower's collateral and debt in a lending reserve
+#[account]
+pub struct Obligation {
+    pub lending_reserve: Pubkey,
+    pub owner: Pubkey,
+    pub collateral: u64,
+    pub borrowed_scaled: u128,
+    pub bump: u8,
+}
+
+impl Obligation {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 16 + 1;
+}
+
+/// A term repo: the seller's vault tokens sit in escrow until the seller repurchases them or the deadline passes
+#[account]
+pub struct RepoAgreement {
+    pub vault: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub repo_id: u64,
+    /// Tokens still in escrow; coupon pass-throughs redeem part of them
+    pub token_amount: u64,
+    pub purchase_price: u64,
+    pub repurchase_price: u64,
+    pub opened_at: i64,
+    pub repurchase_deadline: i64,
+    /// Earliest vault month whose payment has not yet passed through to the seller
+    pub next_coupon_month: u32,
+    pub bump: u8,
+}
+
+impl RepoAgreement {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 4 + 1;
+}
+
+/// Check that the instruction before the current one is an ed25519 program instruction verifying
+/// `signer`'s signature over `message`, with every offset pointing into that instruction
+fn verify_ed25519_signature(instructions: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current_index = instructions_sysvar::load_current_index_checked(instructions)?;
+    require!(current_index > 0, MeteraError::InvalidQuoteSignature);
+    let ix = instructions_sysvar::load_instruction_at_checked(current_index as usize - 1, instructions)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, MeteraError::InvalidQuoteSignature);
+
+    // num_signatures, padding, then signature, public key and message offsets with their instruction indexes
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, MeteraError::InvalidQuoteSignature);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    require!(
+        read_u16(4) == u16::MAX as usize && read_u16(8) == u16::MAX as usize && read_u16(14) == u16::MAX as usize,
+        MeteraError::InvalidQuoteSignature
+    );
+
+    let public_key_offset = read_u16(6);
+    let message_offset = read_u16(10);
+    let message_size = read_u16(12);
+    require!(
+        data.get(public_key_offset..public_key_offset + 32) == Some(signer.as_ref()),
+        MeteraError::InvalidQuoteSignature
+    );
+    require!(
+        data.get(message_offset..message_offset + message_size) == Some(message),
+        MeteraError::InvalidQuoteSignature
+    );
+    Ok(())
+}
+
+/// Payment tokens for `quantity` vault tokens at `discount_bps` below par
+fn order_price(quantity: u64, discount_bps: u16, round_up: bool) -> Result<u64> {
+    let numerator = (quantity as u128)
+        .checked_mul(BPS_DENOMINATOR as u128 - discount_bps as u128)
+        .ok_or(MeteraError::ArithmeticOverflow)?;
+    let price = if round_up {
+        numerator.div_ceil(BPS_DENOMINATOR as u128)
+    } else {
+        numerator / BPS_DENOMINATOR as u128
+    };
+    Ok(u64::try_from(price).map_err(|_| MeteraError::ArithmeticOverflow)?)
+}
+
+/// Part of `liquidity` a Q64.64 `fill` covers
+fn fill_amount(liquidity: u128, fill: u128, round_up: bool) -> Result<u128> {
+    let product = liquidity.checked_mul(fill).ok_or(MeteraError::ArithmeticOverflow)?;
+    let amount = product >> FEE_GROWTH_SHIFT;
+    Ok(if round_up && amount << FEE_GROWTH_SHIFT != product { amount + 1 } else { amount })
+}
+
+/// Vault tokens bought by `payment` at a tick's discount to par
+fn tick_vault_tokens(payment: u128, tick: u8, round_up: bool) -> Result<u128> {
+    let numerator = payment.checked_mul(100).ok_or(MeteraError::ArithmeticOverflow)?;
+    let price = 100 - tick as u128;
+    Ok(if round_up { numerator.div_ceil(price) } else { numerator / price })
+}
+
+/// Payment tokens `vault_tokens` fetch at a tick's discount to par, rounded down
+fn tick_payment(vault_tokens: u128, tick: u8) -> Result<u128> {
+    Ok(vault_tokens.checked_mul(100 - tick as u128).ok_or(MeteraError::ArithmeticOverflow)? / 100)
+}
+
+/// Address of the vault's vesting escrow token account
+fn vesting_escrow_address(vault: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[VESTING_ESCROW_SEED, vault.as_ref()], &crate::ID).0
+}
+
+/// Price of `par_value` tokens sold at `discount_percentage` below par
+fn discounted_price(par_value: u64, discount_percentage: u8) -> Result<u64> {
+    let discount_amount = (par_value as u128)
+        .checked_mul(discount_percentage as u128)
+        .ok_or(MeteraError::ArithmeticOverflow)?
+        .checked_div(100)
+        .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+
+    let price = par_value.checked_sub(discount_amount).ok_or(MeteraError::ArithmeticOverflow)?;
+    Ok(price)
+}
+
+/// Top up a Token-2022 mint so it stays rent-exempt after its metadata grows by `growth` bytes
+fn fund_mint_growth<'info>(
+    mint: &InterfaceAccount<'info, token_interface::Mint>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    growth: usize,
+) -> Result<()> {
+    let mint_info = mint.to_account_info();
+    let new_len = mint_info.data_len().checked_add(growth).ok_or(MeteraError::ArithmeticOverflow)?;
+    let shortfall = Rent::get()?.minimum_balance(new_len).saturating_sub(mint_info.lamports());
+
+    if shortfall > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: mint_info,
+                },
+            ),
+            shortfall,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Address of a vault's protocol or vault-authority fee account for `mint`
+fn fee_account_address(seed: &[u8], vault: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[seed, vault.as_ref(), mint.as_ref()], &crate::ID).0
+}
+
+/// A fee charged on `amount`, split into the protocol's and the vault authority's cuts
+struct FeeCharge {
+    kind: FeeKind,
+    amount: u64,
+    protocol_fee: u64,
+    authority_fee: u64,
+}
+
+impl FeeCharge {
+    fn new(kind: FeeKind, amount: u64, protocol_fee_bps: u16, authority_fee_bps: u16) -> Result<Self> {
+        let cut = |bps: u16| -> Result<u64> {
+            Ok((amount as u128)
+                .checked_mul(bps as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)?
+                .checked_div(BPS_DENOMINATOR as u128)
+                .ok_or(MeteraError::ArithmeticOverflow)? as u64)
+        };
+
+        Ok(Self {
+            kind,
+            amount,
+            protocol_fee: cut(protocol_fee_bps)?,
+            authority_fee: cut(authority_fee_bps)?,
+        })
+    }
+
+    /// What is left of `amount` after both cuts
+    fn net(&self) -> Result<u64> {
+        let total = self.protocol_fee.checked_add(self.authority_fee).ok_or(MeteraError::ArithmeticOverflow)?;
+        Ok(self.amount.checked_sub(total).ok_or(MeteraError::ArithmeticOverflow)?)
+    }
+}
+
+/// Move a payment-token fee from `from` into the vault's fee accounts and itemize it
+#[allow(clippy::too_many_arguments)]
+fn pay_fee<'info>(
+    token_program: &Program<'info, Token>,
+    from: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    protocol_fee_account: &Account<'info, TokenAccount>,
+    authority_fee_account: &Account<'info, TokenAccount>,
+    vault: Pubkey,
+    fee: &FeeCharge,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    for (fee_account, fee_amount) in [(protocol_fee_account, fee.protocol_fee), (authority_fee_account, fee.authority_fee)] {
+        if fee_amount > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    token::Transfer {
+                        from: from.clone(),
+                        to: fee_account.to_account_info(),
+                        authority: authority.clone(),
+                    },
+                    signer_seeds,
+                ),
+                fee_amount,
+            )?;
+        }
+    }
+
+    emit!(FeeCharged {
+        vault,
+        kind: fee.kind,
+        mint: protocol_fee_account.mint,
+        amount: fee.amount,
+        protocol_fee: fee.protocol_fee,
+        authority_fee: fee.authority_fee,
+    });
+
+    Ok(())
+}
+
+/// Accounts one pool swap moves tokens through, oriented from input to output
+struct SwapLeg<'a, 'info> {
+    pool: &'a mut Account<'info, LiquidityPool>,
+    vault: &'a mut Account<'info, Vault>,
+    holding: &'a mut Account<'info, Holding>,
+    in_program: &'a Interface<'info, TokenInterface>,
+    in_mint: &'a InterfaceAccount<'info, token_interface::Mint>,
+    user_in: &'a InterfaceAccount<'info, token_interface::TokenAccount>,
+    pool_in: &'a InterfaceAccount<'info, token_interface::TokenAccount>,
+    out_program: &'a Interface<'info, TokenInterface>,
+    out_mint: &'a InterfaceAccount<'info, token_interface::Mint>,
+    user_out: &'a InterfaceAccount<'info, token_interface::TokenAccount>,
+    pool_out: &'a InterfaceAccount<'info, token_interface::TokenAccount>,
+    protocol_fee_account: &'a InterfaceAccount<'info, token_interface::TokenAccount>,
+    authority_fee_account: &'a InterfaceAccount<'info, token_interface::TokenAccount>,
+    user: AccountInfo<'info>,
+    pool_authority: AccountInfo<'info>,
+}
+
+impl<'info> SwapLeg<'_, 'info> {
+    /// Charge the swap fees, trade the rest along the pool's curve and move the tokens; returns the amount out and the LP fee
+    fn execute(self, amount_in: u64, protocol_swap_fee_bps: u16, hook_accounts: &[AccountInfo<'info>], now: i64) -> Result<(u64, u64)> {
+        require!(amount_in > 0, MeteraError::InvalidSwapAmount);
+
+        let pool = self.pool;
+        require!(pool.is_active, MeteraError::PoolInactive);
+        pool.accumulate_prices(now)?;
+        let a_to_b = self.in_mint.key() == pool.token_a_mint;
+        let (reserve_in, reserve_out) = if a_to_b {
+            (pool.token_a_reserve, pool.token_b_reserve)
+        } else {
+            (pool.token_b_reserve, pool.token_a_reserve)
+        };
+
+        // The swap fee is taken from the input before it reaches the curve
+        let vault = self.vault;
+        let fee = FeeCharge::new(FeeKind::Swap, amount_in, protocol_swap_fee_bps, vault.authority_fees.swap_fee_bps)?;
+        let amount_in_after_fee = fee.net()?;
+
+        // The LP fee stays in the pool vault outside the reserves and accrues to LP positions
+        let lp_fee = (amount_in_after_fee as u128)
+            .checked_mul(pool.lp_fee_bps as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(BPS_DENOMINATOR as u128)
+            .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+        let amount_to_curve = amount_in_after_fee.checked_sub(lp_fee).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        let amount_out = pool.swap_out(amount_to_curve, reserve_in, reserve_out, now)?;
+        require!(amount_out > 0, MeteraError::InsufficientLiquidity);
+
+        if self.user_in.mint == vault.token_mint {
+            vault.record_outgoing(self.holding, amount_in)?;
+        }
+        if self.user_out.mint == vault.token_mint {
+            vault.record_incoming(self.holding, amount_out)?;
+        }
+
+        let vault_key = vault.key();
+        require_keys_eq!(
+            self.protocol_fee_account.key(),
+            fee_account_address(PROTOCOL_FEE_SEED, &vault_key, &self.in_mint.key()),
+            MeteraError::InvalidFeeAccount
+        );
+        require_keys_eq!(
+            self.authority_fee_account.key(),
+            fee_account_address(AUTHORITY_FEE_SEED, &vault_key, &self.in_mint.key()),
+            MeteraError::InvalidFeeAccount
+        );
+
+        for (fee_account, fee_amount) in [
+            (self.protocol_fee_account, fee.protocol_fee),
+            (self.authority_fee_account, fee.authority_fee),
+        ] {
+            if fee_amount > 0 {
+                transfer_checked_with_hook(
+                    self.in_program,
+                    self.user_in,
+                    self.in_mint,
+                    fee_account,
+                    self.user.clone(),
+                    hook_accounts,
+                    fee_amount,
+                    &[],
+                )?;
+            }
+        }
+
+        transfer_checked_with_hook(
+            self.in_program,
+            self.user_in,
+            self.in_mint,
+            self.pool_in,
+            self.user.clone(),
+            hook_accounts,
+            amount_in_after_fee,
+            &[],
+        )?;
+
+        transfer_checked_with_hook(
+            self.out_program,
+            self.pool_out,
+            self.out_mint,
+            self.user_out,
+            self.pool_authority,
+            hook_accounts,
+            amount_out,
+            &[],
+        )?;
+
+        let fee_growth = ((lp_fee as u128) << FEE_GROWTH_SHIFT)
+            .checked_div(pool.total_lp_shares as u128)
+            .ok_or(MeteraError::ZeroLiquidityPool)?;
+        if a_to_b {
+            pool.token_a_reserve = pool.token_a_reserve.checked_add(amount_to_curve).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.token_b_reserve = pool.token_b_reserve.checked_sub(amount_out).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.fee_growth_a = pool.fee_growth_a.checked_add(fee_growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        } else {
+            pool.token_b_reserve = pool.token_b_reserve.checked_add(amount_to_curve).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.token_a_reserve = pool.token_a_reserve.checked_sub(amount_out).ok_or(MeteraError::ArithmeticOverflow)?;
+            pool.fee_growth_b = pool.fee_growth_b.checked_add(fee_growth).ok_or(MeteraError::ArithmeticOverflow)?;
+        }
+
+        emit!(FeeCharged {
+            vault: vault_key,
+            kind: FeeKind::Swap,
+            mint: self.in_mint.key(),
+            amount: amount_in,
+            protocol_fee: fee.protocol_fee,
+            authority_fee: fee.authority_fee,
+        });
+
+        Ok((amount_out, lp_fee))
+    }
+}
+
+/// Output of a constant-product swap of `amount_in` against the given reserves
+fn constant_product_out(amount_in: u64, reserve_in: u64, reserve_out: u64) -> Result<u64> {
+    let amount_out = (amount_in as u128)
+        .checked_mul(reserve_out as u128)
+        .ok_or(MeteraError::ArithmeticOverflow)?
+        .checked_div((reserve_in as u128).checked_add(amount_in as u128).ok_or(MeteraError::ArithmeticOverflow)?)
+        .ok_or(MeteraError::ArithmeticOverflow)? as u64;
+    Ok(amount_out)
+}
+
+/// StableSwap invariant D for two reserves, found by Newton's method
+fn stable_swap_d(amp: u64, reserve_a: u64, reserve_b: u64) -> Result<u128> {
+    let (x, y) = (reserve_a as u128, reserve_b as u128);
+    let sum = x.checked_add(y).ok_or(MeteraError::ArithmeticOverflow)?;
+    if sum == 0 {
+        return Ok(0);
+    }
+
+    let ann = (amp as u128).checked_mul(4).ok_or(MeteraError::ArithmeticOverflow)?;
+    let mut d = sum;
+    for _ in 0..STABLE_SWAP_MAX_ITERATIONS {
+        // D_P = D^3 / (4xy)
+        let d_p = d
+            .checked_mul(d)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(x.checked_mul(2).ok_or(MeteraError::ArithmeticOverflow)?)
+            .ok_or(MeteraError::ZeroLiquidityPool)?
+            .checked_mul(d)
+            .ok_or(MeteraError::ArithmeticOverflow)?
+            .checked_div(y.checked_mul(2).ok_or(MeteraError::ArithmeticOverflow)?)
+            .ok_or(MeteraError::ZeroLiquidityPool)?;
+
+        let previous = d;
+        let numerator = ann
+            .checked_mul(sum)
+            .and_then(|v| v.checked_add(d_p.checked_mul(2)?))
+            .and_then(|v| v.checked_mul(d))
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        let denominator = (ann - 1)
+            .checked_mul(d)
+            .and_then(|v| v.checked_add(d_p.checked_mul(3)?))
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        d = numerator.checked_div(denominator).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        if d.abs_diff(previous) <= 1 {
+            return Ok(d);
+        }
+    }
+
+    err!(MeteraError::CurveNotConverged)
+}
+
+/// Reserve of the other side that keeps invariant `d` once one side holds `reserve_in`
+fn stable_swap_y(amp: u64, reserve_in: u128, d: u128) -> Result<u128> {
+    let ann = (amp as u128).checked_mul(4).ok_or(MeteraError::ArithmeticOverflow)?;
+    // c = D^3 / (4 x Ann), b = x + D / Ann
+    let c = d
+        .checked_mul(d)
+        .ok_or(MeteraError::ArithmeticOverflow)?
+        .checked_div(reserve_in.checked_mul(2).ok_or(MeteraError::ArithmeticOverflow)?)
+        .ok_or(MeteraError::ZeroLiquidityPool)?
+        .checked_mul(d)
+        .ok_or(MeteraError::ArithmeticOverflow)?
+        .checked_div(ann.checked_mul(2).ok_or(MeteraError::ArithmeticOverflow)?)
+        .ok_or(MeteraError::ArithmeticOverflow)?;
+    let b = reserve_in.checked_add(d / ann).ok_or(MeteraError::ArithmeticOverflow)?;
+
+    let mut y = d;
+    for _ in 0..STABLE_SWAP_MAX_ITERATIONS {
+        let previous = y;
+        let numerator = y
+            .checked_mul(y)
+            .and_then(|v| v.checked_add(c))
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        let denominator = y
+            .checked_mul(2)
+            .and_then(|v| v.checked_add(b))
+            .and_then(|v| v.checked_sub(d))
+            .ok_or(MeteraError::ArithmeticOverflow)?;
+        y = numerator.checked_div(denominator).ok_or(MeteraError::ArithmeticOverflow)?;
+
+        if y.abs_diff(previous) <= 1 {
+            return Ok(y);
+        }
+    }
+
+    err!(MeteraError::CurveNotConverged)
+}
+
+/// Output of a StableSwap swap of `amount_in`, rounded down by one unit in the pool's favour
+fn stable_swap_out(amp: u64, amount_in: u64, reserve_in: u64, reserve_out: u64) -> Result<u64> {
+    let d = stable_swap_d(amp, reserve_in, reserve_out)?;
+    let new_reserve_in = (reserve_in as u128).checked_add(amount_in as u128).ok_or(MeteraError::ArithmeticOverflow)?;
+    let new_reserve_out = stable_swap_y(amp, new_reserve_in, d)?;
+    Ok((reserve_out as u128).saturating_sub(new_reserve_out).saturating_sub(1) as u64)
+}
+
+/// Send a closing repo's escrowed tokens to `to` and close the escrow, refunding its rent to `rent_destination`
+#[allow(clippy::too_many_arguments)]
+fn release_repo_escrow<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    escrow: &InterfaceAccount<'info, token_interface::TokenAccount>,
+    mint: &InterfaceAccount<'info, token_interface::Mint>,
+    to: &InterfaceAccount<'info, token_interface::TokenAccount>,
+    vault_signer: &UncheckedAccount<'info>,
+    rent_destination: AccountInfo<'info>,
+    hook_accounts: &[AccountInfo<'info>],
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    if amount > 0 {
+        transfer_checked_with_hook(
+            token_program,
+            escrow,
+            mint,
+            to,
+            vault_signer.to_account_info(),
+            hook_accounts,
+            amount,
+            signer_seeds,
+        )?;
+    }
+    token_interface::close_account(CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::CloseAccount {
+            account: escrow.to_account_info(),
+            destination: rent_destination,
+            authority: vault_signer.to_account_info(),
+        },
+        signer_seeds,
+    ))
+}
+
+/// `transfer_checked` that forwards the extra accounts a Token-2022 transfer hook needs
+#[allow(clippy::too_many_arguments)]
+fn transfer_checked_with_hook<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    from: &InterfaceAccount<'info, token_interface::TokenAccount>,
+    mint: &InterfaceAccount<'info, token_interface::Mint>,
+    to: &InterfaceAccount<'info, token_interface::TokenAccount>,
+    authority: AccountInfo<'info>,
+    hook_accounts: &[AccountInfo<'info>],
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    spl_token_2022::onchain::invoke_transfer_checked(
+        token_program.key,
+        from.to_account_info(),
+        mint.to_account_info(),
+        to.to_account_info(),
+        authority,
+        hook_accounts,
+        amount,
+        mint.decimals,
+        signer_seeds,
+    )?;
+    Ok(())
+}
+
+// ============ EVENTS ============
+
+#[event]
+pub struct VaultCreated {
+    pub vault: Pubkey,
+    pub principal: u64,
+    pub total_expected_interest: u64,
+    pub total_tokens_minted: u64,
+    pub monthly_payment: u64,
+    pub total_months: u32,
+}
+
+#[event]
+pub struct VaultMetadataUpdated {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+#[event]
+pub struct TokensMinted {
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub recipient: Pubkey,
+}
+
+#[event]
+pub struct TokensPurchased {
+    pub vault: Pubkey,
+    pub buyer: Pubkey,
+    pub token_amount: u64,
+    pub purchase_price: u64,
+    pub discount_percentage: u8,
+}
+
+#[event]
+pub struct TokensEscrowed {
+    pub vault: Pubkey,
+    pub offering: Pubkey,
+    pub buyer: Pubkey,
+    pub token_amount: u64,
+    pub purchase_price: u64,
+    pub discount_percentage: u8,
+}
 
-    pub authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct PrimaryOfferingCreated {
+    pub vault: Pubkey,
+    pub offering: Pubkey,
+    pub soft_cap: u64,
+    pub deadline: i64,
 }
 
-#[derive(Accounts)]
-pub struct ReceiveMonthlyPayment<'info> {
-    #[account(mut)]
-    pub vault: Account<'info, Vault>,
+#[event]
+pub struct PrimaryOfferingFinalized {
+    pub vault: Pubkey,
+    pub offering: Pubkey,
+    pub total_committed: u64,
+    pub total_tokens_sold: u64,
+    pub soft_cap: u64,
+    pub succeeded: bool,
+}
 
-    #[account(mut)]
-    pub vault_treasury: Account<'info, TokenAccount>,
+#[event]
+pub struct DutchAuctionStarted {
+    pub vault: Pubkey,
+    pub offering: Pubkey,
+    pub token_cap: u64,
+    pub start_discount_percentage: u8,
+    pub max_discount_percentage: u8,
+    pub settlement: AuctionSettlement,
+}
 
-    #[account(init, payer = payer, space = MonthlyPaymentRecord::INIT_SPACE)]
-    pub monthly_payment_record: Account<'info, MonthlyPaymentRecord>,
+#[event]
+pub struct DutchAuctionCleared {
+    pub vault: Pubkey,
+    pub offering: Pubkey,
+    pub clearing_discount_percentage: u8,
+    pub settlement: AuctionSettlement,
+    pub total_tokens_sold: u64,
+    pub proceeds: u64,
+}
 
-    #[account(mut)]
-    pub payer_account: Account<'info, TokenAccount>,
+#[event]
+pub struct VestingEscrowCreated {
+    pub vault: Pubkey,
+    pub vesting_escrow: Pubkey,
+}
 
-    pub payer: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct VestedTokensClaimed {
+    pub vault: Pubkey,
+    pub buyer: Pubkey,
+    pub primary_sale: Pubkey,
+    pub amount: u64,
+    pub total_released: u64,
 }
 
-#[derive(Accounts)]
-pub struct RedeemTokens<'info> {
-    pub vault: Account<'info, Vault>,
+#[event]
+pub struct BatchAuctionCreated {
+    pub vault: Pubkey,
+    pub auction: Pubkey,
+    pub token_cap: u64,
+    pub commit_deadline: i64,
+    pub reveal_deadline: i64,
+    pub settlement: AuctionSettlement,
+}
 
-    #[account(mut)]
-    pub vault_mut: Account<'info, Vault>,
+#[event]
+pub struct SealedBidCommitted {
+    pub auction: Pubkey,
+    pub bidder: Pubkey,
+    pub escrow_amount: u64,
+}
 
-    #[account(mut)]
-    pub token_mint: Account<'info, Mint>,
+#[event]
+pub struct SealedBidRevealed {
+    pub auction: Pubkey,
+    pub bidder: Pubkey,
+    pub token_amount: u64,
+    pub discount_percentage: u8,
+}
 
-    #[account(mut)]
-    pub vault_treasury: Account<'info, TokenAccount>,
+#[event]
+pub struct BatchAuctionCleared {
+    pub vault: Pubkey,
+    pub auction: Pubkey,
+    pub clearing_discount_percentage: u8,
+    pub total_revealed_demand: u64,
+    pub tokens_allocated: u64,
+}
 
-    #[account(mut)]
-    pub monthly_payment_record: Account<'info, MonthlyPaymentRecord>,
+#[event]
+pub struct SealedBidReclaimed {
+    pub auction: Pubkey,
+    pub bidder: Pubkey,
+    pub refund_amount: u64,
+}
 
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct PrimarySaleRefunded {
+    pub vault: Pubkey,
+    pub offering: Pubkey,
+    pub buyer: Pubkey,
+    pub refund_amount: u64,
+}
 
-    #[account(mut)]
-    pub user_payment_account: Account<'info, TokenAccount>,
+#[event]
+pub struct MonthlyPaymentReceived {
+    pub vault: Pubkey,
+    pub month: u32,
+    pub amount: u64,
+}
 
-    #[account(init, payer = user, space = RedemptionRecord::INIT_SPACE)]
-    pub redemption_record: Account<'info, RedemptionRecord>,
+#[event]
+pub struct PoolRedemption {
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+    pub month: u32,
+    pub payment_amount: u64,
+    pub token_amount: u64,
+    pub redemption_value: u64,
+    pub spread: u64,
+}
 
-    pub user: Signer<'info>,
-    pub vault_authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct TokensRedeemed {
+    pub vault: Pubkey,
+    pub redeemer: Pubkey,
+    pub token_amount: u64,
+    pub redemption_value: u64,
+    pub month: u32,
 }
 
-#[derive(Accounts)]
-pub struct CreateLiquidityPool<'info> {
-    pub vault: Account<'info, Vault>,
+#[event]
+pub struct LiquidityPoolCreated {
+    pub pool: Pubkey,
+    pub vault: Pubkey,
+    pub token_a: Pubkey,
+    pub token_b: Pubkey,
+    pub pool_name: String,
+    pub lp_mint: Pubkey,
+    pub curve: CurveType,
+    pub amp: u64,
+}
 
-    #[account(init, payer = authority, space = LiquidityPool::INIT_SPACE)]
-    pub liquidity_pool: Account<'info, LiquidityPool>,
+#[event]
+pub struct LiquidityProvided {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub token_a_amount: u64,
+    pub token_b_amount: u64,
+    pub lp_shares: u64,
+    pub forward_discount_percentage: u8,
+}
 
-    pub token_a_mint: Account<'info, Mint>,
-    pub token_b_mint: Account<'info, Mint>,
+#[event]
+pub struct LiquidityWithdrawn {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lp_shares: u64,
+    pub token_a_amount: u64,
+    pub token_b_amount: u64,
+}
 
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct TokensSwapped {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub a_to_b: bool,
+    pub lp_fee: u64,
 }
 
-#[derive(Accounts)]
-pub struct ProvideLiquidity<'info> {
-    #[account(mut)]
-    pub liquidity_pool: Account<'info, LiquidityPool>,
+#[event]
+pub struct FlashLoanBorrowed {
+    pub pool: Pubkey,
+    pub borrower: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
 
-    pub vault: Account<'info, Vault>,
+#[event]
+pub struct FlashLoanRepaid {
+    pub pool: Pubkey,
+    pub borrower: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
 
-    #[account(mut)]
-    pub lp_token_a_account: Account<'info, TokenAccount>,
+#[event]
+pub struct PriceObservationsInitialized {
+    pub pool: Pubkey,
+    pub price_observations: Pubkey,
+}
 
-    #[account(mut)]
-    pub lp_token_b_account: Account<'info, TokenAccount>,
+#[event]
+pub struct RouteSwapped {
+    pub user: Pubkey,
+    pub pools: Vec<Pubkey>,
+    pub source_mint: Pubkey,
+    pub destination_mint: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
 
-    #[account(mut)]
-    pub pool_token_a_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct ForwardDiscountRequoted {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lp_position: Pubkey,
+    pub window_number: u64,
+    pub quote_period: u64,
+    pub forward_discount_percentage: u8,
+}
 
-    #[account(mut)]
-    pub pool_token_b_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct LpPositionRolled {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub from_window: u64,
+    pub to_window: u64,
+    pub lp_shares: u64,
+}
 
-    #[account(init, payer = lp, space = LPPosition::INIT_SPACE)]
-    pub lp_position: Account<'info, LPPosition>,
+#[event]
+pub struct LpSharesReleased {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lp_position: Pubkey,
+    pub window_number: u64,
+    pub lp_shares: u64,
+}
 
-    pub lp: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+#[event]
+pub struct AmplificationRamped {
+    pub pool: Pubkey,
+    pub amp_initial: u64,
+    pub amp_target: u64,
+    pub ramp_start: i64,
+    pub ramp_end: i64,
 }
 
-#[derive(Accounts)]
-pub struct WithdrawLiquidity<'info> {
-    #[account(mut)]
-    pub liquidity_pool: Account<'info, LiquidityPool>,
+#[event]
+pub struct DiscountBookCreated {
+    pub pool: Pubkey,
+    pub discount_book: Pubkey,
+}
 
-    #[account(mut)]
-    pub lp_position: Account<'info, LPPosition>,
+#[event]
+pub struct BandLiquidityProvided {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lower_tick: u8,
+    pub upper_tick: u8,
+    pub liquidity: u64,
+    pub vault_token_amount: u64,
+    pub payment_amount: u64,
+}
 
-    #[account(mut)]
-    pub lp_token_a_account: Account<'info, TokenAccount>,
+#[event]
+pub struct BandLiquidityWithdrawn {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lower_tick: u8,
+    pub upper_tick: u8,
+    pub liquidity: u64,
+    pub vault_token_amount: u64,
+    pub payment_amount: u64,
+}
 
-    #[account(mut)]
-    pub lp_token_b_account: Account<'info, TokenAccount>,
+#[event]
+pub struct DiscountBandsSwapped {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub a_to_b: bool,
+    pub start_tick: u8,
+    pub end_tick: u8,
+    pub ticks_crossed: u32,
+}
 
-    #[account(mut)]
-    pub pool_token_a_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct LpFeesClaimed {
+    pub pool: Pubkey,
+    pub lp: Pubkey,
+    pub lp_position: Pubkey,
+    pub token_a_amount: u64,
+    pub token_b_amount: u64,
+}
 
-    #[account(mut)]
-    pub pool_token_b_vault: Account<'info, TokenAccount>,
+#[event]
+pub struct FeeScheduleUpdated {
+    /// `None` for the protocol schedule
+    pub vault: Option<Pubkey>,
+    pub fees: FeeSchedule,
+}
 
-    pub lp: Signer<'info>,
-    pub pool_authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+#[event]
+pub struct FeeAccountsCreated {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub protocol_fee_account: Pubkey,
+    pub authority_fee_account: Pubkey,
 }
 
-#[account]
-pub struct Vault {
-    pub authority: Pubkey,
-    pub token_mint: Pubkey,
-    pub vault_treasury: Pubkey,
-    pub principal: u64,
-    pub total_expected_interest: u64,
-    pub total_tokens_minted: u64,
-    pub monthly_payment: u64,
-    pub total_months: u32,
-    pub current_month: u32,
-    pub vault_name: String,
-    pub created_at: i64,
-    pub total_redeemed: u64,
-    pub is_active: bool,
+#[event]
+pub struct FeeCharged {
+    pub vault: Pubkey,
+    pub kind: FeeKind,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub protocol_fee: u64,
+    pub authority_fee: u64,
 }
 
-impl Vault {
-    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 4 + 4 + 64 + 8 + 8 + 1;
+#[event]
+pub struct FeesCollected {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub protocol_amount: u64,
+    pub authority_amount: u64,
 }
 
-#[account]
-pub struct PrimarySale {
+#[event]
+pub struct AttesterUpdated {
+    pub admin: Pubkey,
+    pub attester: Pubkey,
+}
+
+#[event]
+pub struct InvestorAttested {
+    pub wallet: Pubkey,
+    pub attester: Pubkey,
+    pub kyc_status: KycStatus,
+    pub jurisdiction: [u8; 2],
+    pub is_accredited: bool,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct ComplianceOfficerUpdated {
     pub vault: Pubkey,
-    pub buyer: Pubkey,
-    pub token_amount: u64,
-    pub purchase_price: u64,
-    pub discount_percentage: u8,
-    pub purchased_at: i64,
+    pub compliance_officer: Pubkey,
+}
+
+#[event]
+pub struct HoldingOpened {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+}
+
+#[event]
+pub struct HoldingLimitsUpdated {
+    pub vault: Pubkey,
+    pub max_holding_per_wallet: u64,
+    pub max_holder_count: u32,
 }
 
-impl PrimarySale {
-    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1 + 8;
+#[event]
+pub struct HolderAccountFrozen {
+    pub vault: Pubkey,
+    pub token_account: Pubkey,
+    pub compliance_officer: Pubkey,
+    pub reason_code: u16,
 }
 
-#[account]
-pub struct MonthlyPaymentRecord {
+#[event]
+pub struct HolderAccountThawed {
     pub vault: Pubkey,
-    pub month: u32,
-    pub amount: u64,
-    pub received_at: i64,
-    pub available_for_redemption: u64,
+    pub token_account: Pubkey,
+    pub compliance_officer: Pubkey,
+    pub reason_code: u16,
 }
 
-impl MonthlyPaymentRecord {
-    const INIT_SPACE: usize = 8 + 32 + 4 + 8 + 8 + 8;
+#[event]
+pub struct TokensForceTransferred {
+    pub vault: Pubkey,
+    pub source: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub compliance_officer: Pubkey,
+    pub reason_code: u16,
 }
 
-#[account]
-pub struct RedemptionRecord {
+#[event]
+pub struct OrderBookCreated {
     pub vault: Pubkey,
-    pub redeemer: Pubkey,
-    pub token_amount: u64,
-    pub redemption_value: u64,
-    pub redeemed_at: i64,
-    pub month: u32,
+    pub order_book: Pubkey,
 }
 
-impl RedemptionRecord {
-    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 8 + 4;
+#[event]
+pub struct OrderPlaced {
+    pub order_book: Pubkey,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub discount_bps: u16,
+    pub quantity: u64,
 }
 
-#[account]
-pub struct LiquidityPool {
-    pub vault: Pubkey,
-    pub token_a_mint: Pubkey,
-    pub token_b_mint: Pubkey,
-    pub pool_authority: Pubkey,
-    pub pool_name: String,
-    pub created_at: i64,
-    pub token_a_reserve: u64,
-    pub token_b_reserve: u64,
-    pub total_lp_shares: u64,
-    pub window_start: i64,
-    pub window_number: u64,
-    pub is_active: bool,
+#[event]
+pub struct OrderEvicted {
+    pub order_book: Pubkey,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub quantity: u64,
 }
 
-impl LiquidityPool {
-    const INIT_SPACE: usize = 8 + 32 + 32 + 32 + 32 + 64 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+#[event]
+pub struct OrderCancelled {
+    pub order_book: Pubkey,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub quantity: u64,
 }
 
-#[account]
-pub struct LPPosition {
-    pub pool: Pubkey,
-    pub lp: Pubkey,
-    pub token_a_amount: u64,
-    pub token_b_amount: u64,
-    pub lp_shares: u64,
-    pub forward_discount_percentage: u8,
-    pub window_number: u64,
-    pub provided_at: i64,
+#[event]
+pub struct OrderFilled {
+    pub order_book: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub bid_order_id: u64,
+    pub ask_order_id: u64,
+    pub taker_side: OrderSide,
+    pub quantity: u64,
+    pub discount_bps: u16,
+    pub payment: u64,
 }
 
-impl LPPosition {
-    const INIT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 8 + 1 + 8 + 8;
+#[event]
+pub struct FillsConsumed {
+    pub order_book: Pubkey,
+    pub consumed: u8,
+    pub remaining: u8,
 }
 
-// ============ EVENTS ============
+#[event]
+pub struct FundsSettled {
+    pub order_book: Pubkey,
+    pub owner: Pubkey,
+    pub vault_token_amount: u64,
+    pub payment_amount: u64,
+}
 
 #[event]
-pub struct VaultCreated {
+pub struct RfqFilled {
     pub vault: Pubkey,
-    pub principal: u64,
-    pub total_expected_interest: u64,
-    pub total_tokens_minted: u64,
-    pub monthly_payment: u64,
-    pub total_months: u32,
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub side: OrderSide,
+    pub size: u64,
+    pub discount_bps: u16,
+    pub payment: u64,
+    pub nonce: u64,
 }
 
 #[event]
-pub struct TokensMinted {
+pub struct LendingReserveCreated {
     pub vault: Pubkey,
+    pub lending_reserve: Pubkey,
+    pub max_ltv_bps: u16,
+    pub liquidation_threshold_bps: u16,
+    pub liquidation_bonus_bps: u16,
+    pub borrow_rate_bps: u16,
+}
+
+#[event]
+pub struct ReserveSupplied {
+    pub lending_reserve: Pubkey,
+    pub lender: Pubkey,
     pub amount: u64,
-    pub recipient: Pubkey,
+    pub shares: u64,
 }
 
 #[event]
-pub struct TokensPurchased {
-    pub vault: Pubkey,
-    pub buyer: Pubkey,
-    pub token_amount: u64,
-    pub purchase_price: u64,
-    pub discount_percentage: u8,
+pub struct ReserveWithdrawn {
+    pub lending_reserve: Pubkey,
+    pub lender: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
 }
 
 #[event]
-pub struct MonthlyPaymentReceived {
-    pub vault: Pubkey,
-    pub month: u32,
+pub struct CollateralDeposited {
+    pub lending_reserve: Pubkey,
+    pub owner: Pubkey,
     pub amount: u64,
+    pub collateral: u64,
 }
 
 #[event]
-pub struct TokensRedeemed {
-    pub vault: Pubkey,
-    pub redeemer: Pubkey,
-    pub token_amount: u64,
-    pub redemption_value: u64,
-    pub month: u32,
+pub struct CollateralWithdrawn {
+    pub lending_reserve: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub collateral: u64,
 }
 
 #[event]
-pub struct LiquidityPoolCreated {
-    pub pool: Pubkey,
+pub struct LoanBorrowed {
+    pub lending_reserve: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub debt: u64,
+}
+
+#[event]
+pub struct LoanRepaid {
+    pub lending_reserve: Pubkey,
+    pub owner: Pubkey,
+    pub payer: Pubkey,
+    pub amount: u64,
+    pub remaining_debt: u64,
+}
+
+#[event]
+pub struct ObligationLiquidated {
+    pub lending_reserve: Pubkey,
+    pub owner: Pubkey,
+    pub liquidator: Pubkey,
+    pub repaid: u64,
+    pub collateral_seized: u64,
+    pub remaining_debt: u64,
+}
+
+#[event]
+pub struct RepoOpened {
     pub vault: Pubkey,
-    pub token_a: Pubkey,
-    pub token_b: Pubkey,
-    pub pool_name: String,
+    pub repo_agreement: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub token_amount: u64,
+    pub purchase_price: u64,
+    pub repurchase_price: u64,
+    pub repurchase_deadline: i64,
 }
 
 #[event]
-pub struct LiquidityProvided {
-    pub pool: Pubkey,
-    pub lp: Pubkey,
-    pub token_a_amount: u64,
-    pub token_b_amount: u64,
-    pub lp_shares: u64,
-    pub forward_discount_percentage: u8,
+pub struct RepoCouponPassedThrough {
+    pub repo_agreement: Pubkey,
+    pub seller: Pubkey,
+    pub month: u32,
+    pub token_amount: u64,
+    pub redemption_value: u64,
+    /// Repurchase price left after the redemption value paid it down
+    pub repurchase_price: u64,
 }
 
 #[event]
-pub struct LiquidityWithdrawn {
-    pub pool: Pubkey,
-    pub lp: Pubkey,
-    pub lp_shares: u64,
-    pub token_a_amount: u64,
-    pub token_b_amount: u64,
+pub struct RepoClosed {
+    pub repo_agreement: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub token_amount: u64,
+    /// `false` when the deadline passed and the buyer kept the tokens
+    pub repurchased: bool,
 }
 
 // ============ ERRORS ============
@@ -840,4 +8495,427 @@ pub enum MeteraError {
 
     #[msg("Arithmetic overflow")]
     ArithmeticOverflow,
+
+    #[msg("Offering deadline must be in the future")]
+    InvalidDeadline,
+
+    #[msg("Offering is not open")]
+    OfferingNotOpen,
+
+    #[msg("Offering deadline has passed")]
+    OfferingClosed,
+
+    #[msg("Offering deadline has not passed")]
+    OfferingStillOpen,
+
+    #[msg("Offering did not reach its soft cap")]
+    OfferingNotSucceeded,
+
+    #[msg("Offering has not failed")]
+    OfferingNotFailed,
+
+    #[msg("Invalid offering escrow account")]
+    InvalidOfferingEscrow,
+
+    #[msg("Primary sale is not held in escrow")]
+    SaleNotEscrowed,
+
+    #[msg("Token account is not owned by the expected wallet")]
+    InvalidTokenAccountOwner,
+
+    #[msg("The vault already has an open offering")]
+    OfferingAlreadyActive,
+
+    #[msg("Primary purchases must go through the vault's open offering")]
+    OfferingRequired,
+
+    #[msg("Signer is not the program's upgrade authority")]
+    NotUpgradeAuthority,
+
+    #[msg("Invalid auction terms")]
+    InvalidAuctionTerms,
+
+    #[msg("Auction discount is below the buyer's limit")]
+    AuctionDiscountBelowLimit,
+
+    #[msg("Exceeds offering token cap")]
+    ExceedsOfferingCap,
+
+    #[msg("Refund account required")]
+    MissingRefundAccount,
+
+    #[msg("Auction commit phase has closed")]
+    AuctionCommitClosed,
+
+    #[msg("Auction is not in its reveal phase")]
+    AuctionNotInReveal,
+
+    #[msg("Auction reveal phase is still open")]
+    AuctionRevealOpen,
+
+    #[msg("Auction has not been cleared")]
+    AuctionNotCleared,
+
+    #[msg("Auction has already been cleared")]
+    AuctionAlreadyCleared,
+
+    #[msg("Revealed bid does not match its commitment")]
+    InvalidBidReveal,
+
+    #[msg("Bid escrow does not cover the bid")]
+    InsufficientBidEscrow,
+
+    #[msg("Bid has already been revealed")]
+    BidAlreadyRevealed,
+
+    #[msg("Bid has already been settled")]
+    BidAlreadySettled,
+
+    #[msg("Bid received no allocation")]
+    BidNotWinning,
+
+    #[msg("Bid received an allocation and must be settled")]
+    BidWinning,
+
+    #[msg("Invalid vesting terms")]
+    InvalidVestingTerms,
+
+    #[msg("Vesting purchases must be minted to the vault's vesting escrow")]
+    InvalidVestingEscrow,
+
+    #[msg("Primary sale has no vesting schedule")]
+    SaleNotVesting,
+
+    #[msg("Primary sale has not settled")]
+    SaleNotSettled,
+
+    #[msg("No vested tokens to claim")]
+    NothingVested,
+
+    #[msg("Vesting terms do not match the offering's")]
+    VestingTermsMismatch,
+
+    #[msg("Verified attestations must expire in the future")]
+    InvalidAttestation,
+
+    #[msg("Investor is not KYC verified")]
+    InvestorNotVerified,
+
+    #[msg("Investor attestation has expired")]
+    InvestorAttestationExpired,
+
+    #[msg("Mint does not belong to this pool")]
+    InvalidPoolMint,
+
+    #[msg("Metadata update is empty or a field is too long")]
+    InvalidMetadata,
+
+    #[msg("Force transfer needs a positive amount and distinct accounts")]
+    InvalidForceTransfer,
+
+    #[msg("Swap amount must be positive")]
+    InvalidSwapAmount,
+
+    #[msg("Swap output is below the minimum amount out")]
+    SlippageExceeded,
+
+    #[msg("Pool has insufficient liquidity for this swap")]
+    InsufficientLiquidity,
+
+    #[msg("Holding would exceed the vault's maximum per wallet")]
+    ExceedsMaxHolding,
+
+    #[msg("Vault has reached its maximum holder count")]
+    HolderCountExceeded,
+
+    #[msg("Fee rates exceed the maximum")]
+    InvalidFeeSchedule,
+
+    #[msg("Fee account does not match the vault and mint")]
+    InvalidFeeAccount,
+
+    #[msg("LP position does not belong to this pool")]
+    InvalidLpPosition,
+
+    #[msg("No LP fees to claim")]
+    NoFeesToClaim,
+
+    #[msg("Deposit window does not match the pool's current window")]
+    WindowMismatch,
+
+    #[msg("Liquidity is committed until its window ends")]
+    LiquidityLocked,
+
+    #[msg("Position's window has closed")]
+    WindowClosed,
+
+    #[msg("Forward discount was already quoted this period")]
+    RequoteNotDue,
+
+    #[msg("First deposit must create more than the minimum liquidity")]
+    InsufficientInitialLiquidity,
+
+    #[msg("Pool curve does not support this operation or token pair")]
+    InvalidCurve,
+
+    #[msg("Amplification coefficient or ramp out of bounds")]
+    InvalidAmplification,
+
+    #[msg("StableSwap invariant did not converge")]
+    CurveNotConverged,
+
+    #[msg("Discount band must be non-empty and within 0-99%")]
+    InvalidDiscountBand,
+
+    #[msg("Order needs a quantity and a discount below 100%")]
+    InvalidOrder,
+
+    #[msg("Order book side is full and the order does not beat its worst price")]
+    OrderBookFull,
+
+    #[msg("Order not found for this owner")]
+    OrderNotFound,
+
+    #[msg("No crossing orders to match")]
+    NoCrossingOrders,
+
+    #[msg("Open-orders account belongs to another order book")]
+    InvalidOpenOrders,
+
+    #[msg("Quote has expired")]
+    QuoteExpired,
+
+    #[msg("Quote does not match the accounts provided")]
+    InvalidQuote,
+
+    #[msg("Maker signature over the quote was not verified")]
+    InvalidQuoteSignature,
+
+    #[msg("Swap route accounts are missing or do not chain")]
+    InvalidRoute,
+
+    #[msg("Price history does not cover the requested interval")]
+    OracleHistoryTooShort,
+
+    #[msg("Flash loans must be top-level instructions with a non-zero amount")]
+    InvalidFlashLoan,
+
+    #[msg("A flash loan is already outstanding on this pool")]
+    FlashLoanActive,
+
+    #[msg("No flash_repay for this pool follows in the transaction")]
+    FlashLoanNotRepaid,
+
+    #[msg("Redeeming at par would not beat the pool price by the minimum spread")]
+    NoRedemptionSpread,
+
+    #[msg("LTV must be positive and within the liquidation threshold, which must leave room for the bonus")]
+    InvalidLendingParameters,
+
+    #[msg("Lending amount must be positive and within the account's balance")]
+    InvalidLendingAmount,
+
+    #[msg("Debt would exceed the collateral's borrow limit")]
+    BorrowLimitExceeded,
+
+    #[msg("Obligation is above its liquidation threshold")]
+    ObligationHealthy,
+
+    #[msg("Repo needs tokens, a purchase price, a future deadline and distinct parties")]
+    InvalidRepoTerms,
+
+    #[msg("Repurchase deadline has passed")]
+    RepoExpired,
+
+    #[msg("Repurchase deadline has not passed")]
+    RepoNotExpired,
+
+    #[msg("Monthly payment predates the repo, falls after its deadline or has already passed through")]
+    CouponNotDue,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An account with every field zeroed, so tests set only what they exercise
+    fn zeroed<T: AnchorDeserialize>() -> T {
+        T::deserialize(&mut &[0u8; 4096][..]).unwrap()
+    }
+
+    #[test]
+    fn accrue_fees_credits_growth_since_checkpoint() {
+        let mut position = zeroed::<LPPosition>();
+        position.lp_shares = 1_000;
+
+        position.accrue_fees(3 << FEE_GROWTH_SHIFT, 0).unwrap();
+        assert_eq!((position.fees_owed_a, position.fees_owed_b), (3_000, 0));
+        assert_eq!(position.fee_growth_checkpoint_a, 3 << FEE_GROWTH_SHIFT);
+
+        // Nothing new is earned until the growth moves past the checkpoint
+        position.accrue_fees(3 << FEE_GROWTH_SHIFT, 0).unwrap();
+        assert_eq!(position.fees_owed_a, 3_000);
+    }
+
+    #[test]
+    fn accrue_fees_rounds_down() {
+        let mut position = zeroed::<LPPosition>();
+        position.lp_shares = 3;
+
+        // Half a token per share over three shares is 1.5 tokens
+        position.accrue_fees(0, 1 << (FEE_GROWTH_SHIFT - 1)).unwrap();
+        assert_eq!(position.fees_owed_b, 1);
+    }
+
+    #[test]
+    fn accrue_fees_rejects_growth_below_checkpoint() {
+        let mut position = zeroed::<LPPosition>();
+        position.lp_shares = 1;
+        position.fee_growth_checkpoint_a = 10;
+
+        assert!(position.accrue_fees(5, 0).is_err());
+    }
+
+    #[test]
+    fn stable_swap_d_is_the_sum_of_balanced_reserves() {
+        for amp in [MIN_AMP, 100, MAX_AMP] {
+            let d = stable_swap_d(amp, 1_000_000, 1_000_000).unwrap();
+            assert!(d.abs_diff(2_000_000) <= 1, "amp {amp}: {d}");
+        }
+        assert_eq!(stable_swap_d(100, 0, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn stable_swap_d_converges_between_the_product_and_sum_invariants() {
+        for (x, y) in [(1_000_000u64, 10u64), (5_000_000_000, 3_000_000_000), (7, 1_000_000_000_000)] {
+            let d = stable_swap_d(100, x, y).unwrap();
+            let product = 2 * ((x as u128 * y as u128) as f64).sqrt() as u128;
+            assert!(d >= product && d <= x as u128 + y as u128, "({x}, {y}): {d}");
+        }
+    }
+
+    #[test]
+    fn stable_swap_d_is_symmetric() {
+        assert_eq!(stable_swap_d(85, 1_234_567, 7_654_321).unwrap(), stable_swap_d(85, 7_654_321, 1_234_567).unwrap());
+    }
+
+    #[test]
+    fn stable_swap_y_recovers_the_other_reserve() {
+        let d = stable_swap_d(100, 3_000_000, 2_000_000).unwrap();
+        let y = stable_swap_y(100, 3_000_000, d).unwrap();
+        assert!(y.abs_diff(2_000_000) <= 2, "{y}");
+    }
+
+    #[test]
+    fn stable_swap_out_trades_near_par_on_a_balanced_pool() {
+        let out = stable_swap_out(100, 10_000, 1_000_000, 1_000_000).unwrap();
+        assert!(out < 10_000 && out > 9_900, "{out}");
+    }
+
+    #[test]
+    fn stable_swap_round_trip_returns_no_more_than_was_put_in() {
+        let (reserve_a, reserve_b) = (2_000_000u64, 1_500_000u64);
+        let out = stable_swap_out(100, 50_000, reserve_a, reserve_b).unwrap();
+        let back = stable_swap_out(100, out, reserve_b - out, reserve_a + 50_000).unwrap();
+        assert!(back <= 50_000, "{back}");
+    }
+
+    #[test]
+    fn stable_swap_out_rounds_in_the_pools_favour() {
+        for (amount_in, reserve_in, reserve_out) in [(1u64, 1_000_000u64, 1_000_000u64), (250_000, 1_000_000, 4_000_000), (999, 17, 1_000_000)] {
+            let d = stable_swap_d(100, reserve_in, reserve_out).unwrap();
+            let out = stable_swap_out(100, amount_in, reserve_in, reserve_out).unwrap();
+            let d_after = stable_swap_d(100, reserve_in + amount_in, reserve_out - out).unwrap();
+            assert!(d_after >= d, "({amount_in}, {reserve_in}, {reserve_out}): {d_after} < {d}");
+        }
+    }
+
+    #[test]
+    fn tick_vault_tokens_rounds_as_asked() {
+        assert_eq!(tick_vault_tokens(99, 1, false).unwrap(), 100);
+        assert_eq!(tick_vault_tokens(100, 3, false).unwrap(), 103);
+        assert_eq!(tick_vault_tokens(100, 3, true).unwrap(), 104);
+        assert_eq!(tick_vault_tokens(100, 0, true).unwrap(), 100);
+    }
+
+    #[test]
+    fn tick_payment_rounds_down() {
+        assert_eq!(tick_payment(103, 3).unwrap(), 99);
+        assert_eq!(tick_payment(100, 0).unwrap(), 100);
+        // Paying for tokens rounded up never comes back short
+        assert!(tick_payment(tick_vault_tokens(100, 3, true).unwrap(), 3).unwrap() >= 100);
+    }
+
+    fn discount_book(ticks: &[(usize, u64)]) -> DiscountBook {
+        let mut book = zeroed::<DiscountBook>();
+        for &(tick, liquidity) in ticks {
+            book.tick_liquidity[tick] = liquidity;
+        }
+        book
+    }
+
+    #[test]
+    fn selling_into_the_discount_book_crosses_filled_ticks() {
+        let mut book = discount_book(&[(1, 1_000), (2, 1_000)]);
+
+        // Tick 1 takes ceil(1000 * 100 / 99) = 1011 tokens; the remaining 489 partly fill tick 2 at 98% of par
+        let (amount_out, ticks_crossed) = book.sell_vault_tokens(1_500).unwrap();
+        assert_eq!(amount_out, 1_000 + 479);
+        assert_eq!(ticks_crossed, 2);
+        assert_eq!(book.current_tick, 2);
+        assert!(book.current_fill > 0 && book.current_fill < TICK_FILL_ONE);
+    }
+
+    #[test]
+    fn buying_back_from_the_discount_book_uncrosses_ticks_without_a_profit() {
+        let mut book = discount_book(&[(1, 1_000), (2, 1_000)]);
+        let (paid, _) = book.sell_vault_tokens(1_500).unwrap();
+
+        let (vault_tokens, ticks_crossed) = book.buy_vault_tokens(paid).unwrap();
+        assert!(vault_tokens <= 1_500, "{vault_tokens}");
+        assert_eq!(ticks_crossed, 1);
+        assert_eq!(book.current_tick, 1);
+    }
+
+    #[test]
+    fn selling_past_the_book_liquidity_fails() {
+        let mut book = discount_book(&[(1, 1_000)]);
+        assert!(book.sell_vault_tokens(2_000).is_err());
+    }
+
+    #[test]
+    fn order_price_rounds_as_asked() {
+        assert_eq!(order_price(1_000, 250, false).unwrap(), 975);
+        assert_eq!(order_price(1_001, 250, false).unwrap(), 975);
+        assert_eq!(order_price(1_001, 250, true).unwrap(), 976);
+        assert_eq!(order_price(1_000, 0, true).unwrap(), 1_000);
+        assert_eq!(order_price(u64::MAX, 0, false).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn price_observations_wrap_around_the_ring_buffer() {
+        let mut pool = zeroed::<LiquidityPool>();
+        pool.token_a_reserve = 1_000;
+        pool.token_b_reserve = 2_000;
+        let mut observations = zeroed::<PriceObservations>();
+
+        observations.record(&pool, 0).unwrap();
+        // Within the spacing interval the newest observation stands
+        observations.record(&pool, PRICE_OBSERVATION_SPACING - 1).unwrap();
+        assert_eq!((observations.observations.len(), observations.head), (1, 0));
+
+        let extra = 3;
+        for i in 1..(MAX_PRICE_OBSERVATIONS + extra) as i64 {
+            observations.record(&pool, i * PRICE_OBSERVATION_SPACING).unwrap();
+        }
+        assert_eq!(observations.observations.len(), MAX_PRICE_OBSERVATIONS);
+        assert_eq!(observations.head as usize, extra - 1);
+
+        let newest = &observations.observations[observations.head as usize];
+        assert_eq!(newest.timestamp, (MAX_PRICE_OBSERVATIONS + extra - 1) as i64 * PRICE_OBSERVATION_SPACING);
+        // The oldest slots were overwritten, so the buffer now starts `extra` observations in
+        let oldest = extra as i64 * PRICE_OBSERVATION_SPACING;
+        assert!(observations.at_or_before(oldest - 1).is_none());
+        assert_eq!(observations.at_or_before(oldest).unwrap().timestamp, oldest);
+    }
 }
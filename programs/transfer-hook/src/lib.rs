@@ -0,0 +1,363 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions as instructions_sysvar;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use meteora_tokenization::program::MeteoraTokenization;
+use meteora_tokenization::{
+    InvestorRecord, KycStatus, Vault, HOLDING_SEED, HOOK_AUTHORITY_SEED, INVESTOR_RECORD_SEED, VAULT_SIGNER_SEED,
+    VESTING_AUTHORITY_SEED,
+};
+use spl_tlv_account_resolution::{account::ExtraAccountMeta, seeds::Seed, state::ExtraAccountMetaList};
+use spl_transfer_hook_interface::instruction::ExecuteInstruction;
+
+// Kept separate from the tokenization program: the runtime rejects a program being
+// re-entered through Token-2022, so the vault's own hooked transfers need a distinct hook.
+declare_id!("aNHP1kA5XMUh7ThwMB715gNJc5qczNdW19eYgZGeQba");
+
+pub const EXTRA_ACCOUNT_METAS_SEED: &[u8] = b"extra-account-metas";
+pub const TRANSFER_RULES_SEED: &[u8] = b"transfer_rules";
+pub const MAX_BLOCKED_JURISDICTIONS: usize = 16;
+
+/// Number of extra accounts Token-2022 resolves for each transfer
+const EXTRA_ACCOUNT_COUNT: usize = 8;
+
+#[program]
+pub mod meteora_transfer_hook {
+    use super::*;
+
+    // ============ TRANSFER RULES ============
+
+    /// Register the hook's extra accounts for a vault mint and create its transfer rules
+    pub fn initialize_transfer_hook(ctx: Context<InitializeTransferHook>) -> Result<()> {
+        ExtraAccountMetaList::init::<ExecuteInstruction>(
+            &mut ctx.accounts.extra_account_meta_list.try_borrow_mut_data()?,
+            &extra_account_metas(&ctx.accounts.vault.key())?,
+        )?;
+
+        let rules = &mut ctx.accounts.transfer_rules;
+        rules.vault = ctx.accounts.vault.key();
+        rules.mint = ctx.accounts.token_mint.key();
+        rules.require_accreditation = false;
+        rules.blocked_jurisdictions = Vec::new();
+        rules.bump = ctx.bumps.transfer_rules;
+
+        emit!(TransferRulesUpdated {
+            vault: rules.vault,
+            mint: rules.mint,
+            require_accreditation: false,
+            blocked_jurisdictions: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    /// Replace the accreditation requirement and jurisdiction blocklist for a vault mint
+    pub fn set_transfer_rules(
+        ctx: Context<SetTransferRules>,
+        require_accreditation: bool,
+        blocked_jurisdictions: Vec<[u8; 2]>,
+    ) -> Result<()> {
+        require!(
+            blocked_jurisdictions.len() <= MAX_BLOCKED_JURISDICTIONS,
+            TransferHookError::TooManyJurisdictions
+        );
+
+        let rules = &mut ctx.accounts.transfer_rules;
+        rules.require_accreditation = require_accreditation;
+        rules.blocked_jurisdictions = blocked_jurisdictions.clone();
+
+        emit!(TransferRulesUpdated {
+            vault: rules.vault,
+            mint: rules.mint,
+            require_accreditation,
+            blocked_jurisdictions,
+        });
+
+        Ok(())
+    }
+
+    // ============ TRANSFER HOOK ============
+
+    /// Called by Token-2022 on every transfer; the receiving wallet must be an eligible investor within the vault's holding limits
+    #[interface(spl_transfer_hook_interface::execute)]
+    pub fn transfer_hook(ctx: Context<TransferHook>, amount: u64) -> Result<()> {
+        let recipient = ctx.accounts.destination_token.owner;
+
+        // The vault's own escrows hold tokens on behalf of investors; every other recipient must be eligible
+        let into_escrow = is_vault_escrow_owner(&ctx.accounts.vault.key(), &recipient);
+        if !into_escrow {
+            let record_info = ctx.accounts.destination_investor_record.to_account_info();
+            require!(
+                record_info.owner == &meteora_tokenization::ID && !record_info.data_is_empty(),
+                TransferHookError::RecipientNotVerified
+            );
+            let record = InvestorRecord::try_deserialize(&mut &record_info.try_borrow_data()?[..])?;
+
+            let rules = &ctx.accounts.transfer_rules;
+            require!(record.kyc_status == KycStatus::Verified, TransferHookError::RecipientNotVerified);
+            require!(Clock::get()?.unix_timestamp < record.expires_at, TransferHookError::RecipientAttestationExpired);
+            require!(!rules.require_accreditation || record.is_accredited, TransferHookError::RecipientNotAccredited);
+            require!(
+                !rules.blocked_jurisdictions.contains(&record.jurisdiction),
+                TransferHookError::JurisdictionBlocked
+            );
+        }
+
+        // Holding limits and the holder count live on the vault, so the tokenization program applies them.
+        // Its own transfers already record them, and the runtime would reject re-entering it from here.
+        if !invoked_by_tokenization_program(&ctx.accounts.instructions_sysvar)? {
+            let seeds: &[&[u8]] = &[HOOK_AUTHORITY_SEED, &[ctx.bumps.hook_authority]];
+            meteora_tokenization::cpi::record_secondary_transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.tokenization_program.to_account_info(),
+                    meteora_tokenization::cpi::accounts::RecordSecondaryTransfer {
+                        vault: ctx.accounts.vault.to_account_info(),
+                        token_mint: ctx.accounts.mint.to_account_info(),
+                        source_token_account: ctx.accounts.source_token.to_account_info(),
+                        destination_token_account: ctx.accounts.destination_token.to_account_info(),
+                        source_holding: ctx.accounts.source_holding.to_account_info(),
+                        destination_holding: (!into_escrow).then(|| ctx.accounts.destination_holding.to_account_info()),
+                        hook_authority: ctx.accounts.hook_authority.to_account_info(),
+                    },
+                    &[seeds],
+                ),
+                amount,
+            )?;
+        }
+
+        emit!(TransferApproved {
+            mint: ctx.accounts.mint.key(),
+            source: ctx.accounts.source_token.key(),
+            destination: ctx.accounts.destination_token.key(),
+            recipient,
+            amount,
+        });
+
+        Ok(())
+    }
+}
+
+// ============ ACCOUNTS & STRUCTS ============
+
+#[derive(Accounts)]
+pub struct InitializeTransferHook<'info> {
+    #[account(has_one = authority, has_one = token_mint)]
+    pub vault: Account<'info, Vault>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: TLV validation account read by Token-2022; written via `ExtraAccountMetaList::init`
+    #[account(
+        init,
+        payer = authority,
+        space = ExtraAccountMetaList::size_of(EXTRA_ACCOUNT_COUNT)?,
+        seeds = [EXTRA_ACCOUNT_METAS_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = TransferRules::INIT_SPACE,
+        seeds = [TRANSFER_RULES_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub transfer_rules: Account<'info, TransferRules>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetTransferRules<'info> {
+    #[account(has_one = authority)]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, has_one = vault, seeds = [TRANSFER_RULES_SEED, transfer_rules.mint.as_ref()], bump = transfer_rules.bump)]
+    pub transfer_rules: Account<'info, TransferRules>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferHook<'info> {
+    #[account(token::mint = mint)]
+    pub source_token: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(token::mint = mint)]
+    pub destination_token: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: source owner or delegate; Token-2022 has already checked its signature
+    pub owner: UncheckedAccount<'info>,
+
+    /// CHECK: TLV validation account; Token-2022 resolved the accounts below from it
+    #[account(seeds = [EXTRA_ACCOUNT_METAS_SEED, mint.key().as_ref()], bump)]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    #[account(seeds = [TRANSFER_RULES_SEED, mint.key().as_ref()], bump = transfer_rules.bump)]
+    pub transfer_rules: Account<'info, TransferRules>,
+
+    pub tokenization_program: Program<'info, MeteoraTokenization>,
+
+    /// CHECK: the receiving wallet's investor record, which may not exist yet
+    #[account(
+        seeds = [INVESTOR_RECORD_SEED, destination_token.owner.as_ref()],
+        bump,
+        seeds::program = tokenization_program.key()
+    )]
+    pub destination_investor_record: UncheckedAccount<'info>,
+
+    /// CHECK: instructions sysvar, checked by address
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    /// CHECK: the mint's vault, checked against the transfer rules; only written through the tokenization program
+    #[account(mut, address = transfer_rules.vault)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: PDA the tokenization program accepts as the hook's signer; holds no data
+    #[account(seeds = [HOOK_AUTHORITY_SEED], bump)]
+    pub hook_authority: UncheckedAccount<'info>,
+
+    /// CHECK: the sending wallet's holding; deserialized by the tokenization program
+    #[account(
+        mut,
+        seeds = [HOLDING_SEED, vault.key().as_ref(), source_token.owner.as_ref()],
+        bump,
+        seeds::program = tokenization_program.key()
+    )]
+    pub source_holding: UncheckedAccount<'info>,
+
+    /// CHECK: the receiving wallet's holding; deserialized by the tokenization program
+    #[account(
+        mut,
+        seeds = [HOLDING_SEED, vault.key().as_ref(), destination_token.owner.as_ref()],
+        bump,
+        seeds::program = tokenization_program.key()
+    )]
+    pub destination_holding: UncheckedAccount<'info>,
+}
+
+#[account]
+pub struct TransferRules {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub require_accreditation: bool,
+    pub blocked_jurisdictions: Vec<[u8; 2]>,
+    pub bump: u8,
+}
+
+impl TransferRules {
+    const INIT_SPACE: usize = 8 + 32 + 32 + 1 + 4 + 2 * MAX_BLOCKED_JURISDICTIONS + 1;
+}
+
+/// Accounts appended to every `execute`, in the order `TransferHook` expects them
+fn extra_account_metas(vault: &Pubkey) -> Result<Vec<ExtraAccountMeta>> {
+    Ok(vec![
+        // index 5: transfer rules for this mint
+        ExtraAccountMeta::new_with_seeds(
+            &[
+                Seed::Literal { bytes: TRANSFER_RULES_SEED.to_vec() },
+                Seed::AccountKey { index: 1 },
+            ],
+            false,
+            false,
+        )?,
+        // index 6: tokenization program, which owns the investor registry
+        ExtraAccountMeta::new_with_pubkey(&meteora_tokenization::ID, false, false)?,
+        // index 7: investor record of the destination token account's owner
+        ExtraAccountMeta::new_external_pda_with_seeds(
+            6,
+            &[
+                Seed::Literal { bytes: INVESTOR_RECORD_SEED.to_vec() },
+                Seed::AccountData { account_index: 2, data_index: 32, length: 32 },
+            ],
+            false,
+            false,
+        )?,
+        // index 8: instructions sysvar
+        ExtraAccountMeta::new_with_pubkey(&instructions_sysvar::ID, false, false)?,
+        // index 9: the mint's vault, which tracks holdings
+        ExtraAccountMeta::new_with_pubkey(vault, false, true)?,
+        // index 10: hook authority that signs the holdings update
+        ExtraAccountMeta::new_with_seeds(&[Seed::Literal { bytes: HOOK_AUTHORITY_SEED.to_vec() }], false, false)?,
+        // index 11: holding of the source token account's owner
+        ExtraAccountMeta::new_external_pda_with_seeds(
+            6,
+            &[
+                Seed::Literal { bytes: HOLDING_SEED.to_vec() },
+                Seed::AccountKey { index: 9 },
+                Seed::AccountData { account_index: 0, data_index: 32, length: 32 },
+            ],
+            false,
+            true,
+        )?,
+        // index 12: holding of the destination token account's owner
+        ExtraAccountMeta::new_external_pda_with_seeds(
+            6,
+            &[
+                Seed::Literal { bytes: HOLDING_SEED.to_vec() },
+                Seed::AccountKey { index: 9 },
+                Seed::AccountData { account_index: 2, data_index: 32, length: 32 },
+            ],
+            false,
+            true,
+        )?,
+    ])
+}
+
+/// Whether `owner` is one of the tokenization program's PDAs that custody a vault's tokens in escrow
+fn is_vault_escrow_owner(vault: &Pubkey, owner: &Pubkey) -> bool {
+    [VAULT_SIGNER_SEED, VESTING_AUTHORITY_SEED]
+        .iter()
+        .any(|seed| Pubkey::find_program_address(&[seed, vault.as_ref()], &meteora_tokenization::ID).0 == *owner)
+}
+
+/// Whether the transaction's current top-level instruction targets the tokenization program
+fn invoked_by_tokenization_program(instructions: &AccountInfo) -> Result<bool> {
+    let current_index = instructions_sysvar::load_current_index_checked(instructions)?;
+    let current_ix = instructions_sysvar::load_instruction_at_checked(current_index as usize, instructions)?;
+    Ok(current_ix.program_id == meteora_tokenization::ID)
+}
+
+// ============ EVENTS ============
+
+#[event]
+pub struct TransferRulesUpdated {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub require_accreditation: bool,
+    pub blocked_jurisdictions: Vec<[u8; 2]>,
+}
+
+#[event]
+pub struct TransferApproved {
+    pub mint: Pubkey,
+    pub source: Pubkey,
+    pub destination: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+// ============ ERRORS ============
+
+#[error_code]
+pub enum TransferHookError {
+    #[msg("Recipient is not a KYC-verified investor")]
+    RecipientNotVerified,
+
+    #[msg("Recipient's investor attestation has expired")]
+    RecipientAttestationExpired,
+
+    #[msg("Recipient must be an accredited investor")]
+    RecipientNotAccredited,
+
+    #[msg("Recipient's jurisdiction is blocked for this vault")]
+    JurisdictionBlocked,
+
+    #[msg("Too many blocked jurisdictions")]
+    TooManyJurisdictions,
+}
@@ -0,0 +1,402 @@
+use anchor_lang::prelude::{AccountInfo, Pubkey};
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::solana_program::{instruction::AccountMeta, instruction::Instruction, system_instruction, system_program, sysvar};
+use anchor_lang::{AccountSerialize, InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use anchor_spl::token_2022::spl_token_2022;
+use anchor_spl::token_2022::spl_token_2022::extension::ExtensionType;
+use meteora_tokenization::{
+    FeeSchedule, KycStatus, MeteraError, ProtocolConfig, VaultTerms, HOLDING_SEED, HOOK_AUTHORITY_SEED, INVESTOR_RECORD_SEED,
+    PROTOCOL_CONFIG_SEED, VAULT_SIGNER_SEED,
+};
+use meteora_transfer_hook::{TransferHookError, EXTRA_ACCOUNT_METAS_SEED, TRANSFER_RULES_SEED};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::AccountSharedData;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+const DECIMALS: u8 = 6;
+
+// Anchor's entrypoints tie the account slice to the account lifetime; program-test does not
+fn tokenization_entry(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    meteora_tokenization::entry(program_id, accounts, data)
+}
+
+fn transfer_hook_entry(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    meteora_transfer_hook::entry(program_id, accounts, data)
+}
+
+struct VaultFixture {
+    vault: Pubkey,
+    token_mint: Pubkey,
+}
+
+async fn process(ctx: &mut ProgramTestContext, ixs: &[Instruction], signers: &[&Keypair]) -> Result<(), BanksClientError> {
+    // A fresh blockhash keeps a retried transfer from being deduplicated
+    let blockhash = ctx.get_new_latest_blockhash().await?;
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(ixs, Some(&ctx.payer.pubkey()), &all_signers, blockhash);
+    ctx.banks_client.process_transaction(tx).await
+}
+
+fn assert_custom_error(result: Result<(), BanksClientError>, expected: impl Into<u32>) {
+    match result.expect_err("transfer should have been rejected").unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => assert_eq!(code, expected.into()),
+        other => panic!("unexpected error: {other:?}"),
+    }
+}
+
+fn protocol_config_address() -> Pubkey {
+    Pubkey::find_program_address(&[PROTOCOL_CONFIG_SEED], &meteora_tokenization::ID).0
+}
+
+fn investor_record_address(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[INVESTOR_RECORD_SEED, wallet.as_ref()], &meteora_tokenization::ID).0
+}
+
+async fn attest(ctx: &mut ProgramTestContext, wallet: &Pubkey, jurisdiction: [u8; 2]) {
+    let ix = Instruction {
+        program_id: meteora_tokenization::ID,
+        accounts: meteora_tokenization::accounts::AttestInvestor {
+            protocol_config: protocol_config_address(),
+            investor_record: investor_record_address(wallet),
+            wallet: *wallet,
+            attester: ctx.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: meteora_tokenization::instruction::AttestInvestor {
+            kyc_status: KycStatus::Verified,
+            jurisdiction,
+            is_accredited: false,
+            expires_at: i64::MAX,
+        }
+        .data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+fn holding_address(vault: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[HOLDING_SEED, vault.as_ref(), owner.as_ref()], &meteora_tokenization::ID).0
+}
+
+async fn open_holding(ctx: &mut ProgramTestContext, fixture: &VaultFixture, owner: &Pubkey) {
+    let ix = Instruction {
+        program_id: meteora_tokenization::ID,
+        accounts: meteora_tokenization::accounts::OpenHolding {
+            vault: fixture.vault,
+            holding: holding_address(&fixture.vault, owner),
+            owner: *owner,
+            payer: ctx.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: meteora_tokenization::instruction::OpenHolding {}.data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+}
+
+async fn create_token_account(ctx: &mut ProgramTestContext, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+    let account = Keypair::new();
+    let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&[ExtensionType::TransferHookAccount]).unwrap();
+    let lamports = ctx.banks_client.get_rent().await.unwrap().minimum_balance(space);
+    let ixs = [
+        system_instruction::create_account(&ctx.payer.pubkey(), &account.pubkey(), lamports, space as u64, &spl_token_2022::ID),
+        spl_token_2022::instruction::initialize_account3(&spl_token_2022::ID, &account.pubkey(), mint, owner).unwrap(),
+    ];
+    process(ctx, &ixs, &[&account]).await.unwrap();
+    account.pubkey()
+}
+
+/// Protocol config, a Token-2022 vault mint with the hook registered, and its transfer rules
+async fn setup() -> (ProgramTestContext, VaultFixture) {
+    let mut program_test = ProgramTest::new("meteora_tokenization", meteora_tokenization::ID, processor!(tokenization_entry));
+    program_test.add_program("meteora_transfer_hook", meteora_transfer_hook::ID, processor!(transfer_hook_entry));
+    let mut ctx = program_test.start_with_context().await;
+    let authority = ctx.payer.pubkey();
+
+    // Builtin processors have no program data account to name an upgrade authority, so seed the config directly
+    let config = ProtocolConfig {
+        admin: authority,
+        attester: authority,
+        protocol_fees: FeeSchedule::default(),
+        bump: Pubkey::find_program_address(&[PROTOCOL_CONFIG_SEED], &meteora_tokenization::ID).1,
+    };
+    let mut data = Vec::new();
+    config.try_serialize(&mut data).unwrap();
+    let lamports = ctx.banks_client.get_rent().await.unwrap().minimum_balance(data.len());
+    let mut config_account = AccountSharedData::new(lamports, data.len(), &meteora_tokenization::ID);
+    config_account.set_data_from_slice(&data);
+    ctx.set_account(&protocol_config_address(), &config_account);
+
+    let payment_mint = Keypair::new();
+    let lamports = ctx.banks_client.get_rent().await.unwrap().minimum_balance(spl_token::state::Mint::LEN);
+    let payment_mint_ixs = [
+        system_instruction::create_account(&authority, &payment_mint.pubkey(), lamports, spl_token::state::Mint::LEN as u64, &spl_token::ID),
+        spl_token::instruction::initialize_mint2(&spl_token::ID, &payment_mint.pubkey(), &authority, None, DECIMALS).unwrap(),
+    ];
+    process(&mut ctx, &payment_mint_ixs, &[&payment_mint]).await.unwrap();
+
+    let vault = Keypair::new();
+    let token_mint = Keypair::new();
+    let vault_treasury = Keypair::new();
+    let create_vault_ix = Instruction {
+        program_id: meteora_tokenization::ID,
+        accounts: meteora_tokenization::accounts::CreateVault {
+            vault: vault.pubkey(),
+            vault_signer: Pubkey::find_program_address(&[VAULT_SIGNER_SEED, vault.pubkey().as_ref()], &meteora_tokenization::ID).0,
+            token_mint: token_mint.pubkey(),
+            payment_mint: payment_mint.pubkey(),
+            vault_treasury: vault_treasury.pubkey(),
+            authority,
+            token_program: spl_token::ID,
+            vault_token_program: spl_token_2022::ID,
+            system_program: system_program::ID,
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: meteora_tokenization::instruction::CreateVault {
+            terms: VaultTerms {
+                principal: 1_000_000_000,
+                total_expected_interest: 100_000_000,
+                monthly_payment: 91_666_666,
+                total_months: 12,
+            },
+            vault_name: "Invoice Pool".to_string(),
+            symbol: "INVP".to_string(),
+            uri: "https://example.com/invoice-pool.json".to_string(),
+        }
+        .data(),
+    };
+    process(&mut ctx, &[create_vault_ix], &[&vault, &token_mint, &vault_treasury]).await.unwrap();
+
+    let hook_ix = Instruction {
+        program_id: meteora_transfer_hook::ID,
+        accounts: meteora_transfer_hook::accounts::InitializeTransferHook {
+            vault: vault.pubkey(),
+            token_mint: token_mint.pubkey(),
+            extra_account_meta_list: extra_account_metas_address(&token_mint.pubkey()),
+            transfer_rules: transfer_rules_address(&token_mint.pubkey()),
+            authority,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: meteora_transfer_hook::instruction::InitializeTransferHook {}.data(),
+    };
+    process(&mut ctx, &[hook_ix], &[]).await.unwrap();
+
+    let fixture = VaultFixture { vault: vault.pubkey(), token_mint: token_mint.pubkey() };
+    (ctx, fixture)
+}
+
+fn extra_account_metas_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[EXTRA_ACCOUNT_METAS_SEED, mint.as_ref()], &meteora_transfer_hook::ID).0
+}
+
+fn transfer_rules_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[TRANSFER_RULES_SEED, mint.as_ref()], &meteora_transfer_hook::ID).0
+}
+
+/// Mint vault tokens to a fresh holder account owned by `owner`, whose holding must already be open
+async fn fund_holder(ctx: &mut ProgramTestContext, fixture: &VaultFixture, owner: &Pubkey, amount: u64) -> Pubkey {
+    let account = create_token_account(ctx, &fixture.token_mint, owner).await;
+    let ix = Instruction {
+        program_id: meteora_tokenization::ID,
+        accounts: meteora_tokenization::accounts::MintTokens {
+            vault: fixture.vault,
+            token_mint: fixture.token_mint,
+            destination: account,
+            destination_holding: holding_address(&fixture.vault, owner),
+            authority: ctx.payer.pubkey(),
+            token_program: spl_token_2022::ID,
+        }
+        .to_account_metas(None),
+        data: meteora_tokenization::instruction::MintTokens { amount }.data(),
+    };
+    process(ctx, &[ix], &[]).await.unwrap();
+    account
+}
+
+/// Secondary-market `transfer_checked` with the hook's extra accounts appended
+fn hooked_transfer(fixture: &VaultFixture, source: &Pubkey, destination: &Pubkey, destination_owner: &Pubkey, owner: &Pubkey, amount: u64) -> Instruction {
+    let mut ix = spl_token_2022::instruction::transfer_checked(
+        &spl_token_2022::ID,
+        source,
+        &fixture.token_mint,
+        destination,
+        owner,
+        &[],
+        amount,
+        DECIMALS,
+    )
+    .unwrap();
+    ix.accounts.extend([
+        AccountMeta::new_readonly(transfer_rules_address(&fixture.token_mint), false),
+        AccountMeta::new_readonly(meteora_tokenization::ID, false),
+        AccountMeta::new_readonly(investor_record_address(destination_owner), false),
+        AccountMeta::new_readonly(sysvar::instructions::ID, false),
+        AccountMeta::new(fixture.vault, false),
+        AccountMeta::new_readonly(Pubkey::find_program_address(&[HOOK_AUTHORITY_SEED], &meteora_transfer_hook::ID).0, false),
+        AccountMeta::new(holding_address(&fixture.vault, owner), false),
+        AccountMeta::new(holding_address(&fixture.vault, destination_owner), false),
+        AccountMeta::new_readonly(meteora_transfer_hook::ID, false),
+        AccountMeta::new_readonly(extra_account_metas_address(&fixture.token_mint), false),
+    ]);
+    ix
+}
+
+async fn balance(ctx: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    let data = ctx.banks_client.get_account(*account).await.unwrap().unwrap().data;
+    spl_token_2022::extension::StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data).unwrap().base.amount
+}
+
+#[tokio::test]
+async fn transfer_to_unverified_wallet_is_blocked() {
+    let (mut ctx, fixture) = setup().await;
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    attest(&mut ctx, &alice.pubkey(), *b"SG").await;
+    open_holding(&mut ctx, &fixture, &alice.pubkey()).await;
+    open_holding(&mut ctx, &fixture, &bob.pubkey()).await;
+
+    let alice_account = fund_holder(&mut ctx, &fixture, &alice.pubkey(), 1_000).await;
+    let bob_account = create_token_account(&mut ctx, &fixture.token_mint, &bob.pubkey()).await;
+
+    let transfer = hooked_transfer(&fixture, &alice_account, &bob_account, &bob.pubkey(), &alice.pubkey(), 400);
+    let result = process(&mut ctx, std::slice::from_ref(&transfer), &[&alice]).await;
+    assert_custom_error(result, TransferHookError::RecipientNotVerified);
+    assert_eq!(balance(&mut ctx, &bob_account).await, 0);
+
+    attest(&mut ctx, &bob.pubkey(), *b"SG").await;
+    process(&mut ctx, &[transfer], &[&alice]).await.unwrap();
+    assert_eq!(balance(&mut ctx, &alice_account).await, 600);
+    assert_eq!(balance(&mut ctx, &bob_account).await, 400);
+}
+
+#[tokio::test]
+async fn transfer_into_blocked_jurisdiction_is_rejected() {
+    let (mut ctx, fixture) = setup().await;
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    attest(&mut ctx, &alice.pubkey(), *b"SG").await;
+    attest(&mut ctx, &bob.pubkey(), *b"US").await;
+    open_holding(&mut ctx, &fixture, &alice.pubkey()).await;
+    open_holding(&mut ctx, &fixture, &bob.pubkey()).await;
+
+    let rules_ix = Instruction {
+        program_id: meteora_transfer_hook::ID,
+        accounts: meteora_transfer_hook::accounts::SetTransferRules {
+            vault: fixture.vault,
+            transfer_rules: transfer_rules_address(&fixture.token_mint),
+            authority: ctx.payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: meteora_transfer_hook::instruction::SetTransferRules {
+            require_accreditation: false,
+            blocked_jurisdictions: vec![*b"US"],
+        }
+        .data(),
+    };
+    process(&mut ctx, &[rules_ix], &[]).await.unwrap();
+
+    let alice_account = fund_holder(&mut ctx, &fixture, &alice.pubkey(), 1_000).await;
+    let bob_account = create_token_account(&mut ctx, &fixture.token_mint, &bob.pubkey()).await;
+
+    let transfer = hooked_transfer(&fixture, &alice_account, &bob_account, &bob.pubkey(), &alice.pubkey(), 400);
+    let result = process(&mut ctx, &[transfer], &[&alice]).await;
+    assert_custom_error(result, TransferHookError::JurisdictionBlocked);
+    assert_eq!(balance(&mut ctx, &alice_account).await, 1_000);
+}
+
+#[tokio::test]
+async fn holding_limits_apply_to_secondary_transfers() {
+    let (mut ctx, fixture) = setup().await;
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    attest(&mut ctx, &alice.pubkey(), *b"SG").await;
+    attest(&mut ctx, &bob.pubkey(), *b"SG").await;
+    open_holding(&mut ctx, &fixture, &alice.pubkey()).await;
+    open_holding(&mut ctx, &fixture, &bob.pubkey()).await;
+
+    let alice_account = fund_holder(&mut ctx, &fixture, &alice.pubkey(), 1_000).await;
+    let bob_account = create_token_account(&mut ctx, &fixture.token_mint, &bob.pubkey()).await;
+
+    let set_limits = |ctx: &ProgramTestContext, max_holding_per_wallet: u64, max_holder_count: u32| Instruction {
+        program_id: meteora_tokenization::ID,
+        accounts: meteora_tokenization::accounts::SetHoldingLimits {
+            vault: fixture.vault,
+            authority: ctx.payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: meteora_tokenization::instruction::SetHoldingLimits { max_holding_per_wallet, max_holder_count }.data(),
+    };
+
+    let ix = set_limits(&ctx, 500, 0);
+    process(&mut ctx, &[ix], &[]).await.unwrap();
+    let transfer = hooked_transfer(&fixture, &alice_account, &bob_account, &bob.pubkey(), &alice.pubkey(), 600);
+    let result = process(&mut ctx, &[transfer], &[&alice]).await;
+    assert_custom_error(result, MeteraError::ExceedsMaxHolding);
+
+    let ix = set_limits(&ctx, 500, 1);
+    process(&mut ctx, &[ix], &[]).await.unwrap();
+    let transfer = hooked_transfer(&fixture, &alice_account, &bob_account, &bob.pubkey(), &alice.pubkey(), 400);
+    let result = process(&mut ctx, &[transfer], &[&alice]).await;
+    assert_custom_error(result, MeteraError::HolderCountExceeded);
+    assert_eq!(balance(&mut ctx, &bob_account).await, 0);
+}
+
+#[tokio::test]
+async fn holding_limit_covers_every_account_a_wallet_holds() {
+    let (mut ctx, fixture) = setup().await;
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    attest(&mut ctx, &alice.pubkey(), *b"SG").await;
+    attest(&mut ctx, &bob.pubkey(), *b"SG").await;
+    open_holding(&mut ctx, &fixture, &alice.pubkey()).await;
+    open_holding(&mut ctx, &fixture, &bob.pubkey()).await;
+
+    let alice_account = fund_holder(&mut ctx, &fixture, &alice.pubkey(), 1_000).await;
+    let bob_first = create_token_account(&mut ctx, &fixture.token_mint, &bob.pubkey()).await;
+    let bob_second = create_token_account(&mut ctx, &fixture.token_mint, &bob.pubkey()).await;
+
+    let limits_ix = Instruction {
+        program_id: meteora_tokenization::ID,
+        accounts: meteora_tokenization::accounts::SetHoldingLimits {
+            vault: fixture.vault,
+            authority: ctx.payer.pubkey(),
+        }
+        .to_account_metas(None),
+        data: meteora_tokenization::instruction::SetHoldingLimits { max_holding_per_wallet: 500, max_holder_count: 0 }.data(),
+    };
+    process(&mut ctx, &[limits_ix], &[]).await.unwrap();
+
+    let transfer = hooked_transfer(&fixture, &alice_account, &bob_first, &bob.pubkey(), &alice.pubkey(), 400);
+    process(&mut ctx, &[transfer], &[&alice]).await.unwrap();
+
+    // A second account does not give the wallet a fresh allowance
+    let transfer = hooked_transfer(&fixture, &alice_account, &bob_second, &bob.pubkey(), &alice.pubkey(), 200);
+    let result = process(&mut ctx, &[transfer], &[&alice]).await;
+    assert_custom_error(result, MeteraError::ExceedsMaxHolding);
+    assert_eq!(balance(&mut ctx, &bob_second).await, 0);
+}
+
+#[tokio::test]
+async fn transfer_into_vault_escrow_skips_investor_checks() {
+    let (mut ctx, fixture) = setup().await;
+    let alice = Keypair::new();
+    attest(&mut ctx, &alice.pubkey(), *b"SG").await;
+    open_holding(&mut ctx, &fixture, &alice.pubkey()).await;
+
+    let vault_signer = Pubkey::find_program_address(&[VAULT_SIGNER_SEED, fixture.vault.as_ref()], &meteora_tokenization::ID).0;
+    let alice_account = fund_holder(&mut ctx, &fixture, &alice.pubkey(), 1_000).await;
+    let escrow = create_token_account(&mut ctx, &fixture.token_mint, &vault_signer).await;
+
+    let transfer = hooked_transfer(&fixture, &alice_account, &escrow, &vault_signer, &alice.pubkey(), 400);
+    process(&mut ctx, &[transfer], &[&alice]).await.unwrap();
+    assert_eq!(balance(&mut ctx, &escrow).await, 400);
+}